@@ -1,5 +1,6 @@
 use super::value_parsers;
-use clap::{arg, ArgMatches, Command};
+use crate::utils::manifest::MANIFEST_FILE;
+use clap::{arg, Arg, ArgAction, ArgMatches, Command};
 
 pub fn read_input() -> ArgMatches {
     Command::new("spsd")
@@ -7,6 +8,7 @@ pub fn read_input() -> ArgMatches {
         .version("0.1")
         .about("A utility for managing state persistent serverless devboxes")
         .subcommand_required(true)
+        .arg(arg!(--profile <name> "Profile to use instead of the active one").global(true))
         .subcommand(
             Command::new("new")
                 .about("Create a new instance")
@@ -28,13 +30,21 @@ pub fn read_input() -> ArgMatches {
                     arg!(<region> "Region of instance")
                         .value_parser(value_parsers::parse_region)
                         .required(false),
-                ).arg(arg!(<port> "Port to expose (optional)").value_parser(value_parsers::parse_port).required(false))
+                ).arg(port_arg())
+                .arg(env_arg())
+                .arg(env_file_arg())
+                .arg(arg!(--cmd <command> "Command to run instead of sleeping, replacing the image's CMD").value_parser(value_parsers::parse_command))
+                .arg(arg!(--entrypoint <command> "Entrypoint to run instead of sleeping, replacing the image's ENTRYPOINT").value_parser(value_parsers::parse_command))
+                .arg(arg!(--"keep-image-cmd" "Run the image's own ENTRYPOINT and CMD instead of sleeping"))
+                .arg(arg!(--"no-wait" "Return once the instance is created, without starting it to pull its image"))
+                .arg(output_arg())
                 .after_help("Please note fly enforces cpu/memory ratios that may render your configuration invalid"),
         )
         .subcommand(
             Command::new("start")
                 .about("Start and connect to an instance")
                 .arg(arg!(<name> "Name of instance").required(true))
+                .arg(connector_arg())
                 .arg_required_else_help(true),
         )
         .subcommand(
@@ -46,8 +56,139 @@ pub fn read_input() -> ArgMatches {
             Command::new("sl")
             .about("Run an instance serverlessly")
             .arg(arg!(<name> "Name of instance").required(true))
+            .arg(connector_arg())
+            .arg(arg!(--"idle-timeout" <minutes> "Stop the instance after this many idle minutes (0 to disable)")
+                .value_parser(value_parsers::parse_idle_timeout)
+                .default_value("30"))
             .arg_required_else_help(true),
         )
+        .subcommand(
+            Command::new("resize")
+            .visible_alias("update")
+            .about("Change the specs of an existing instance")
+            .arg(arg!(<name> "Name of instance").required(true))
+            .arg(arg!(--image <image> "Url of image (\"base\" for ubuntu)").value_parser(value_parsers::parse_image))
+            .arg(arg!(--cpus <cpus> "Number of CPUs (1, 2, 4, 8, 12, 16)").value_parser(value_parsers::parse_cpu))
+            .arg(arg!(--memory <memory> "Amount of memory (256 - 32768 mb)").value_parser(value_parsers::parse_memory))
+            .arg(arg!(--volume <volume> "Size of volume, can only grow (1-500 gb)").value_parser(value_parsers::parse_volume))
+            .arg(port_arg())
+            .arg(env_arg())
+            .arg(env_file_arg())
+            .arg(arg!(--"unset-env" <key> "Remove an environment variable, can be repeated").action(ArgAction::Append))
+            .arg(output_arg())
+            .arg_required_else_help(true)
+            .after_help("Please note fly enforces cpu/memory ratios that may render your configuration invalid"),
+        )
+        .subcommand(
+            Command::new("clone")
+            .about("Create a copy of an instance, including its volume")
+            .arg(arg!(<source> "Name of instance to copy").required(true))
+            .arg(arg!(<name> "Name of the new instance").required(true))
+            .arg(arg!(--region <region> "Region of the new instance (defaults to the source's)").value_parser(value_parsers::parse_region))
+            .arg(port_arg())
+            .arg(output_arg())
+            .arg_required_else_help(true),
+        )
+        .subcommand(
+            Command::new("cp")
+            .about("Copy files between this machine and an instance")
+            .arg(arg!(<source> "Local path or <name>:<path> on the instance").required(true))
+            .arg(arg!(<destination> "Local path or <name>:<path> on the instance").required(true))
+            .arg(arg!(-r --recursive "Copy directories recursively"))
+            .arg(connector_arg())
+            .arg_required_else_help(true)
+            .after_help("Relative paths on the instance are resolved against /data"),
+        )
+        .subcommand(
+            Command::new("exec")
+            .about("Run a command on an instance, starting it if needed")
+            .arg(arg!(<name> "Name of instance").required(true))
+            .arg(arg!(<command> ... "Command to run").required(true).last(true))
+            .arg(arg!(--timeout <seconds> "Seconds to wait for the command to finish")
+                .value_parser(clap::value_parser!(u64).range(1..=3600))
+                .default_value("60"))
+            .arg(arg!(--stop "Also stop the instance afterwards if it was already running"))
+            .arg(connector_arg())
+            .arg_required_else_help(true)
+            .after_help("spsd exits with the command's exit code, e.g. spsd exec devbox -- make test. \
+                The command runs over the connector, so its output streams while it runs, which \
+                fly's exec endpoint doesn't do. A stopped instance is stopped again afterwards"),
+        )
+        .subcommand(
+            Command::new("forward")
+            .about("Forward a port on an instance to localhost without exposing it publicly")
+            .arg(arg!(<name> "Name of instance").required(true))
+            .arg(arg!(<ports> "Port on the instance, optionally followed by the local port (8080[:3000])")
+                .required(true)
+                .value_parser(value_parsers::parse_forward))
+            .arg(connector_arg())
+            .arg_required_else_help(true),
+        )
+        .subcommand(
+            Command::new("secrets")
+            .about("Manage secrets passed to every instance as environment variables")
+            .subcommand_required(true)
+            .subcommand(
+                Command::new("list")
+                .about("List secret names and digests")
+                .arg(output_arg()))
+            .subcommand(
+                Command::new("set")
+                .about("Set secrets")
+                .arg(arg!(<secrets> ... "Secrets to set (KEY=VALUE)").required(true).value_parser(value_parsers::parse_env))
+                .arg(stage_arg()))
+            .subcommand(
+                Command::new("unset")
+                .about("Remove secrets")
+                .arg(arg!(<keys> ... "Names of the secrets to remove").required(true))
+                .arg(stage_arg())),
+        )
+        .subcommand(
+            Command::new("wait")
+            .about("Wait for an instance to reach a state")
+            .arg(arg!(<name> "Name of instance").required(true))
+            .arg(arg!(--state <state> "State to wait for (started, stopped, suspended, destroyed)").value_parser(value_parsers::parse_wait_state).default_value("started"))
+            .arg(arg!(--timeout <seconds> "Seconds to wait before giving up").value_parser(clap::value_parser!(u64).range(1..)).default_value("300"))
+            .arg_required_else_help(true))
+        .subcommand(
+            Command::new("ip")
+            .about("Manage the public IP addresses of your instances")
+            .subcommand_required(true)
+            .subcommand(
+                Command::new("list")
+                .about("List allocated IP addresses")
+                .arg(output_arg()))
+            .subcommand(
+                Command::new("allocate")
+                .about("Allocate a dedicated IPv4 address ($2/mo)")
+                .arg(arg!(--v6 "Allocate a dedicated IPv6 address instead (free)"))
+                .arg(arg!(--shared "Allocate the shared IPv4 address instead (free, http and tls on 80 and 443 only)").conflicts_with("v6"))
+                .arg(arg!(--region <region> "Region to allocate the address in (global by default)").value_parser(value_parsers::parse_region)))
+            .subcommand(
+                Command::new("release")
+                .about("Release an IP address")
+                .arg(arg!(<address> "Address to release").required(true))),
+        )
+        .subcommand(
+            Command::new("snapshot")
+            .about("Create, list and restore snapshots of an instance's volume")
+            .subcommand_required(true)
+            .subcommand(
+                Command::new("create")
+                .about("Snapshot an instance's volume")
+                .arg(arg!(<name> "Name of instance").required(true)))
+            .subcommand(
+                Command::new("list")
+                .about("List snapshots of an instance's volume")
+                .arg(arg!(<name> "Name of instance").required(true))
+                .arg(output_arg()))
+            .subcommand(
+                Command::new("restore")
+                .about("Replace a stopped instance's volume with one created from a snapshot")
+                .arg(arg!(<name> "Name of instance").required(true))
+                .arg(arg!(--snapshot <id> "Snapshot to restore (defaults to the latest)"))
+                .arg(arg!(--"delete-old" "Delete the volume that is being replaced instead of keeping it"))),
+        )
         .subcommand(
             Command::new("destroy")
                 .about("Destroy an instance")
@@ -55,12 +196,78 @@ pub fn read_input() -> ArgMatches {
                 .arg_required_else_help(true),
         ).subcommand(
             Command::new("profile")
-            .about("Set fly.io profile")
-            .arg(arg!(<api_key> "Fly api key (overwrites existing)").required(true))
-            .arg(arg!(<allocate_ip> "Allocate a dedicated IPv4 address (optional)").required(true).value_parser(["y", "n"])))
+            .about("Manage fly.io profiles (or set the active one's api key)")
+            .args_conflicts_with_subcommands(true)
+            .arg(arg!(<api_key> "Fly api key (overwrites existing)").required(false))
+            .arg(arg!(<allocate_ip> "Allocate a dedicated IPv4 address (optional)").required(false).value_parser(["y", "n"]))
+            .subcommand(
+                Command::new("add")
+                .about("Create a profile, or update an existing one")
+                .arg(arg!(<name> "Name of profile").required(true))
+                .arg(arg!(<api_key> "Fly api key").required(true))
+                .arg(arg!(<allocate_ip> "Allocate a dedicated IPv4 address").required(true).value_parser(["y", "n"]))
+                .arg(arg!(--org <org> "Slug of the fly organization the app belongs to (default personal)"))
+                .arg(arg!(--app <app> "Name of the app to use, created if it doesn't exist (generated by default)"))
+                .arg(arg!(--region <region> "Default region for new instances").value_parser(value_parsers::parse_region))
+                .arg(arg!(--image <image> "Default image for new instances").value_parser(value_parsers::parse_image))
+                .arg(arg!(--cpus <cpus> "Default number of CPUs for new instances").value_parser(value_parsers::parse_cpu))
+                .arg(arg!(--memory <memory> "Default memory for new instances (mb)").value_parser(value_parsers::parse_memory))
+                .arg(arg!(--volume <volume> "Default volume size for new instances (gb)").value_parser(value_parsers::parse_volume)))
+            .subcommand(
+                Command::new("use")
+                .about("Make a profile the active one")
+                .arg(arg!(<name> "Name of profile").required(true)))
+            .subcommand(
+                Command::new("list")
+                .about("List profiles")
+                .arg(output_arg())))
         .subcommand(
             Command::new("list")
             .about("List instances and attached IPs")
-            .arg(arg!(<ip> "List attached IPv4 addresses").required(false)))
+            .arg(arg!(<ip> "Only list IP addresses (same as `spsd ip list`)").required(false).value_parser(["ip"]))
+            .arg(output_arg()))
+        .subcommand(
+            Command::new("apply")
+            .about("Create or update instances to match a spsd.toml file")
+            .arg(arg!(-f --file <path> "Path to the instance definitions").default_value(MANIFEST_FILE))
+            .arg(arg!(--"dry-run" "Only show what would change"))
+            .arg(output_arg()))
+        .subcommand(
+            Command::new("usage")
+            .about("Show hours run and estimated cost per instance and month")
+            .arg(arg!(<name> "Name of instance (optional)").required(false))
+            .arg(arg!(--month <month> "Only show this month (YYYY-MM)").value_parser(value_parsers::parse_month))
+            .arg(output_arg()))
         .get_matches()
 }
+
+fn output_arg() -> Arg {
+    arg!(-o --output <format> "Output format (table, json, yaml, csv)")
+        .value_parser(value_parsers::parse_output)
+        .default_value("table")
+}
+
+fn port_arg() -> Arg {
+    arg!(-p --port <port> "Port to expose, can be repeated ([public:]internal[/handlers|tcp|udp])")
+        .value_parser(value_parsers::parse_port_spec)
+        .action(ArgAction::Append)
+}
+
+fn env_arg() -> Arg {
+    arg!(-e --env <pair> "Environment variable to set (KEY=VALUE), can be repeated")
+        .value_parser(value_parsers::parse_env)
+        .action(ArgAction::Append)
+}
+
+fn env_file_arg() -> Arg {
+    arg!(--"env-file" <path> "File of KEY=VALUE lines to set as environment variables")
+}
+
+fn stage_arg() -> Arg {
+    arg!(--stage "Don't update instances now, they get the change with their next update")
+}
+
+fn connector_arg() -> Arg {
+    arg!(-c --connector <connector> "How to connect to the instance (flyctl, ssh)")
+        .value_parser(value_parsers::parse_connector)
+}
@@ -1,17 +1,35 @@
 use super::value_parsers::{
-    parse_cpu, parse_image, parse_memory, parse_port, parse_region, parse_volume,
+    parse_cpu, parse_image, parse_memory, parse_port_spec, parse_region, parse_volume,
 };
-use crate::utils::types::{Instance, InstanceInput, InstanceState};
-use colored::Colorize;
-use std::{
-    error::Error,
-    io::{self, Write},
+use crate::utils::error::SpsdError;
+use crate::utils::manifest::{PlanAction, PlanEntry};
+use crate::utils::types::{
+    Instance, InstanceInput, InstanceState, IpAddress, OutputFormat, PortSpec, ProfileSummary,
+    Secret, Snapshot,
 };
+use crate::utils::usage::UsageSummary;
+use colored::{ColoredString, Colorize};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::io::{self, Write};
+use std::time::Duration;
 extern crate prettytable;
 use prettytable::{row, Cell, Row, Table};
 
-pub fn display_error(err: Box<dyn Error>) {
-    println!("\n{}: {}", "ERROR".red(), err);
+pub fn display_error(err: &SpsdError) {
+    eprintln!("\n{}: {}", "ERROR".red(), err);
+    if let Some(hint) = err.hint() {
+        eprintln!("{}: {}", "HINT".yellow(), hint);
+    }
+}
+
+pub fn display_warning(message: &str) {
+    eprintln!("{}: {}", "WARNING".yellow(), message);
+}
+
+// progress and status notes go to stderr, keeping stdout for results
+pub fn display_message(message: &str) {
+    eprintln!("{}", message);
 }
 
 pub fn display_success(message: &str) {
@@ -20,7 +38,77 @@ pub fn display_success(message: &str) {
     }
 }
 
-pub fn display_instances(instances: Vec<Instance>) {
+// prints data in a machine-readable format, falling back to `display_table` for tables
+pub fn display_data<T: Serialize>(
+    data: &T,
+    format: OutputFormat,
+    display_table: impl FnOnce(),
+) -> Result<(), SpsdError> {
+    match format {
+        OutputFormat::Table => display_table(),
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(data)?),
+        OutputFormat::Yaml => print!("{}", serde_yaml::to_string(data).map_err(io::Error::other)?),
+        OutputFormat::Csv => write_csv(serde_json::to_value(data)?)?,
+    }
+    Ok(())
+}
+
+fn write_csv(data: Value) -> Result<(), SpsdError> {
+    let rows: Vec<Map<String, Value>> = match data {
+        Value::Array(items) => items.into_iter().map(flatten_row).collect(),
+        item => vec![flatten_row(item)],
+    };
+    let mut writer = csv::Writer::from_writer(io::stdout());
+    if let Some(first) = rows.first() {
+        writer.write_record(first.keys()).map_err(io::Error::from)?;
+    }
+    for row in rows {
+        let record = row.values().map(|value| match value {
+            Value::String(string) => string.clone(),
+            Value::Null => String::new(),
+            value => value.to_string(),
+        });
+        writer.write_record(record).map_err(io::Error::from)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+// nested objects become dotted columns (specs.cpu_count) so every row is flat
+fn flatten_row(item: Value) -> Map<String, Value> {
+    let mut row = Map::new();
+    flatten_into(&mut row, None, item);
+    row
+}
+
+fn flatten_into(row: &mut Map<String, Value>, prefix: Option<&str>, value: Value) {
+    match value {
+        Value::Object(fields) => {
+            for (key, value) in fields {
+                let key = match prefix {
+                    Some(prefix) => format!("{}.{}", prefix, key),
+                    None => key,
+                };
+                flatten_into(row, Some(&key), value);
+            }
+        }
+        value => {
+            row.insert(prefix.unwrap_or("value").to_string(), value);
+        }
+    }
+}
+
+pub fn display_instances(
+    instances: Vec<Instance>,
+    addresses: &[IpAddress],
+    format: OutputFormat,
+) -> Result<(), SpsdError> {
+    display_data(&instances, format, || {
+        print_instance_table(&instances, addresses)
+    })
+}
+
+fn print_instance_table(instances: &[Instance], addresses: &[IpAddress]) {
     let mut table = Table::new();
 
     table.add_row(row![
@@ -30,7 +118,8 @@ pub fn display_instances(instances: Vec<Instance>) {
         "Memory".blue(),
         "Volume".blue(),
         "Region".blue(),
-        "Port".blue(),
+        "Ports".blue(),
+        "Reachable at".blue(),
         "State".blue()
     ]);
     for instance in instances {
@@ -41,22 +130,204 @@ pub fn display_instances(instances: Vec<Instance>) {
             Cell::new(&format!("{} mb", instance.specs.memory_mb)),
             Cell::new(&format!("{} gb", instance.specs.volume_gb)),
             Cell::new(&instance.region),
+            Cell::new(
+                &instance
+                    .ports
+                    .iter()
+                    .map(PortSpec::to_string)
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            ),
+            Cell::new(
+                &instance
+                    .ports
+                    .iter()
+                    .flat_map(|port| {
+                        addresses
+                            .iter()
+                            .filter(|address| address.reaches(port))
+                            .map(|address| address.endpoint(port.public_port))
+                    })
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            ),
+            Cell::new(&format!("{}", colored_state(instance.state))),
+        ]));
+    }
+    table.printstd();
+}
+
+// green once it's up, red when it's down or broken and yellow while it's changing
+fn colored_state(state: InstanceState) -> ColoredString {
+    let name = state.to_string();
+    match state {
+        InstanceState::Started => name.green(),
+        InstanceState::Stopped | InstanceState::Suspended => name.red(),
+        InstanceState::Destroyed | InstanceState::Failed => name.bright_red().bold(),
+        InstanceState::Unknown => name.dimmed(),
+        _ => name.yellow(),
+    }
+}
+
+pub fn display_snapshots(snapshots: Vec<Snapshot>, format: OutputFormat) -> Result<(), SpsdError> {
+    display_data(&snapshots, format, || {
+        let mut table = Table::new();
+        table.add_row(row![
+            "ID".blue(),
+            "Created".blue(),
+            "Size".blue(),
+            "Status".blue()
+        ]);
+        for snapshot in &snapshots {
+            table.add_row(Row::new(vec![
+                Cell::new(&snapshot.id),
+                Cell::new(&snapshot.created_at),
+                Cell::new(&format!(
+                    "{:.2} gb",
+                    snapshot.size as f64 / (1024.0 * 1024.0 * 1024.0)
+                )),
+                Cell::new(&snapshot.status),
+            ]));
+        }
+        table.printstd();
+    })
+}
+
+// secret values never leave fly, only their digests are shown
+pub fn display_secrets(secrets: Vec<Secret>, format: OutputFormat) -> Result<(), SpsdError> {
+    display_data(&secrets, format, || {
+        let mut table = Table::new();
+        table.add_row(row!["Name".blue(), "Digest".blue(), "Created".blue()]);
+        for secret in &secrets {
+            table.add_row(row![secret.name, secret.digest, secret.created_at]);
+        }
+        table.printstd();
+    })
+}
+
+pub fn display_ips(addresses: Vec<IpAddress>, format: OutputFormat) -> Result<(), SpsdError> {
+    display_data(&addresses, format, || {
+        let mut table = Table::new();
+        table.add_row(row![
+            "Address".blue(),
+            "Type".blue(),
+            "Region".blue(),
+            "Created".blue()
+        ]);
+        for address in &addresses {
+            table.add_row(row![
+                address.address,
+                address.ip_type,
+                address.region.as_deref().unwrap_or("-"),
+                address.created_at.as_deref().unwrap_or("-")
+            ]);
+        }
+        table.printstd();
+    })
+}
+
+pub fn display_profiles(
+    profiles: Vec<ProfileSummary>,
+    format: OutputFormat,
+) -> Result<(), SpsdError> {
+    display_data(&profiles, format, || {
+        let mut table = Table::new();
+        table.add_row(row![
+            "".blue(),
+            "Profile".blue(),
+            "App".blue(),
+            "Org".blue(),
+            "Region".blue()
+        ]);
+        for profile in &profiles {
+            table.add_row(row![
+                if profile.active { "*" } else { "" },
+                profile.name,
+                profile.app.as_deref().unwrap_or("-"),
+                profile.org.as_deref().unwrap_or("-"),
+                profile.region.as_deref().unwrap_or("-")
+            ]);
+        }
+        table.printstd();
+    })
+}
+
+pub fn display_plan(entries: &[PlanEntry]) {
+    for entry in entries {
+        match entry.action {
+            PlanAction::Create => println!("{} {} (create)", "+".green(), entry.name),
+            PlanAction::Update => println!("{} {} (update)", "~".yellow(), entry.name),
+            PlanAction::Unchanged => println!("{} {} (unchanged)", "=".blue(), entry.name),
+        }
+        for change in &entry.changes {
+            println!(
+                "    {}: {} -> {}",
+                change.field, change.current, change.desired
+            );
+        }
+        for drift in &entry.drift {
+            println!(
+                "    {} {}: {} -> {} (can't be changed in place)",
+                "drift".red(),
+                drift.field,
+                drift.current,
+                drift.desired
+            );
+        }
+    }
+}
+
+pub fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    match (seconds / 3600, seconds % 3600 / 60, seconds % 60) {
+        (0, 0, seconds) => format!("{}s", seconds),
+        (0, minutes, seconds) => format!("{}m {}s", minutes, seconds),
+        (hours, minutes, _) => format!("{}h {}m", hours, minutes),
+    }
+}
+
+pub fn display_usage(summaries: Vec<UsageSummary>, format: OutputFormat) -> Result<(), SpsdError> {
+    display_data(&summaries, format, || print_usage_table(&summaries))
+}
+
+fn print_usage_table(summaries: &[UsageSummary]) {
+    let mut table = Table::new();
+
+    table.add_row(row![
+        "Name".blue(),
+        "Month".blue(),
+        "Hours".blue(),
+        "CPUs".blue(),
+        "Memory".blue(),
+        "Volume".blue(),
+        "Est. cost".blue()
+    ]);
+    for summary in summaries {
+        table.add_row(Row::new(vec![
+            Cell::new(&summary.instance),
+            Cell::new(&summary.month),
+            Cell::new(&format!("{:.2}", summary.hours)),
             Cell::new(&format!(
-                "{}",
-                match instance.port {
-                    Some(port) => port.to_string(),
-                    None => String::new(),
-                }
-            )),
-            Cell::new(&format!(
-                "{}",
-                match instance.state {
-                    InstanceState::Running => "Running".green(),
-                    InstanceState::Stopped => "Stopped".red(),
-                }
+                "{} {}",
+                summary.specs.cpu_count, summary.specs.cpu_kind
             )),
+            Cell::new(&format!("{} mb", summary.specs.memory_mb)),
+            Cell::new(&format!("{} gb", summary.specs.volume_gb)),
+            Cell::new(&format!("${:.2}", summary.total_cost)),
         ]));
     }
+    let total = summaries
+        .iter()
+        .fold(0.0, |total, summary| total + summary.total_cost);
+    table.add_row(Row::new(vec![
+        Cell::new(&"Total".blue().to_string()),
+        Cell::new(""),
+        Cell::new(""),
+        Cell::new(""),
+        Cell::new(""),
+        Cell::new(""),
+        Cell::new(&format!("${:.2}", total)),
+    ]));
     table.printstd();
 }
 
@@ -104,15 +375,34 @@ pub fn prompt_instance_creation(mut instance: InstanceInput) -> InstanceInput {
         Some(_) => instance.region,
         None => get_user_input("Region: ").and_then(|input| parse_region(&input).ok()),
     };
-    instance.port = match &instance.port {
-        Some(_) => instance.port,
-        None => get_user_input("Port (optional, enter to continue): ").and_then(|input| {
-            if input.is_empty() {
-                None
-            } else {
-                parse_port(&input).ok()
-            }
-        }),
-    };
+    if instance.ports.is_empty() {
+        instance.ports = get_user_input("Ports (optional, separated by spaces): ")
+            .map(|input| {
+                input
+                    .split_whitespace()
+                    .filter_map(|port| parse_port_spec(port).ok())
+                    .collect()
+            })
+            .unwrap_or_default();
+    }
     instance
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn flatten_row_uses_dotted_keys_in_field_order() {
+        let row = flatten_row(json!({
+            "name": "devbox",
+            "specs": { "cpu_count": 2, "memory_mb": 512 },
+            "port": null
+        }));
+
+        let keys: Vec<&String> = row.keys().collect();
+        assert_eq!(keys, ["name", "specs.cpu_count", "specs.memory_mb", "port"]);
+        assert_eq!(row["specs.memory_mb"], json!(512));
+    }
+}
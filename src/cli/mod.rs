@@ -1,3 +1,4 @@
 pub mod arg_parsing;
 pub mod io;
+pub mod progress;
 pub mod value_parsers;
@@ -0,0 +1,49 @@
+use crate::utils::error::SpsdError;
+use colored::Colorize;
+use indicatif::{ProgressBar, ProgressStyle};
+use std::future::Future;
+use std::io::{self, IsTerminal};
+use std::time::{Duration, Instant};
+
+// reports the steps of a long running operation on stderr, one spinner per step. stays quiet when
+// stderr isn't a terminal so scripts and piped output don't get spinner noise
+pub struct Progress {
+    enabled: bool,
+}
+
+impl Progress {
+    pub fn new() -> Self {
+        Progress {
+            enabled: io::stderr().is_terminal(),
+        }
+    }
+
+    pub fn hidden() -> Self {
+        Progress { enabled: false }
+    }
+
+    // runs `future` behind a spinner, then leaves a line saying how it went and how long it took
+    pub async fn step<T, F>(&self, message: &str, future: F) -> Result<T, SpsdError>
+    where
+        F: Future<Output = Result<T, SpsdError>>,
+    {
+        if !self.enabled {
+            return future.await;
+        }
+
+        let spinner = ProgressBar::new_spinner()
+            .with_style(ProgressStyle::with_template("{spinner} {msg} {elapsed}").unwrap())
+            .with_message(message.to_string());
+        spinner.enable_steady_tick(Duration::from_millis(100));
+        let started = Instant::now();
+        let result = future.await;
+        spinner.finish_and_clear();
+
+        let seconds = started.elapsed().as_secs_f64();
+        match &result {
+            Ok(_) => eprintln!("{} {} ({:.1}s)", "✓".green(), message, seconds),
+            Err(_) => eprintln!("{} {} ({:.1}s)", "✗".red(), message, seconds),
+        }
+        result
+    }
+}
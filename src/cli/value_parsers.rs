@@ -1,7 +1,10 @@
+use crate::utils::env::parse_env_var;
+use crate::utils::types::{ConnectorKind, OutputFormat, PortForward, PortSpec, WaitState};
+
 pub fn parse_cpu(count: &str) -> Result<u32, String> {
     let value: u32 = count.parse().map_err(|_| "Invalid number of CPUs")?;
 
-    let allowed_cpu_counts = vec![1, 2, 4, 8, 12, 16];
+    let allowed_cpu_counts = [1, 2, 4, 8, 12, 16];
     if allowed_cpu_counts.contains(&value) {
         Ok(value)
     } else {
@@ -25,7 +28,7 @@ pub fn parse_memory(amount: &str) -> Result<u32, String> {
 
     let rounded_value = (value + 128) / 256 * 256;
 
-    if rounded_value >= 256 && rounded_value <= 32768 {
+    if (256..=32768).contains(&rounded_value) {
         Ok(rounded_value)
     } else {
         Err("Memory must be between 256 and 32768".to_string())
@@ -35,25 +38,35 @@ pub fn parse_memory(amount: &str) -> Result<u32, String> {
 pub fn parse_volume(size: &str) -> Result<u32, String> {
     let value: u32 = size.parse().map_err(|_| "Invalid volume size")?;
 
-    if value >= 1 && value <= 500 {
+    if (1..=500).contains(&value) {
         Ok(value)
     } else {
         Err("Size of volume must be between 1 and 500 gb".to_string())
     }
 }
 
-pub fn parse_port(port: &str) -> Result<u16, String> {
-    let value: u16 = port.parse().map_err(|_| "Invalid port")?;
+pub fn parse_port_spec(spec: &str) -> Result<PortSpec, String> {
+    spec.parse()
+}
 
-    if value >= 1024 {
-        Ok(value)
-    } else {
-        Err("Port must be between 1024 and 65535".to_string())
+pub fn parse_env(pair: &str) -> Result<(String, String), String> {
+    parse_env_var(pair)
+}
+
+pub fn parse_command(command: &str) -> Result<Vec<String>, String> {
+    let words = shell_words::split(command).map_err(|error| error.to_string())?;
+    if words.is_empty() {
+        return Err(String::from("Command can't be empty"));
     }
+    Ok(words)
+}
+
+pub fn parse_wait_state(state: &str) -> Result<WaitState, String> {
+    state.parse()
 }
 
 pub fn parse_region(region: &str) -> Result<String, String> {
-    let allowed_regions = vec![
+    let allowed_regions = [
         "ams", "bom", "cdg", "dfw", "fra", "hkg", "iad", "lax", "lhr", "nrt", "ord", "scl", "sea",
         "sin", "sjc", "syd", "yyz",
     ];
@@ -64,3 +77,54 @@ pub fn parse_region(region: &str) -> Result<String, String> {
         Err("Invalid region".to_string())
     }
 }
+
+pub fn parse_output(format: &str) -> Result<OutputFormat, String> {
+    match format {
+        "table" => Ok(OutputFormat::Table),
+        "json" => Ok(OutputFormat::Json),
+        "yaml" => Ok(OutputFormat::Yaml),
+        "csv" => Ok(OutputFormat::Csv),
+        _ => Err("Output format must be one of [table, json, yaml, csv]".to_string()),
+    }
+}
+
+pub fn parse_connector(kind: &str) -> Result<ConnectorKind, String> {
+    kind.parse()
+}
+
+pub fn parse_idle_timeout(minutes: &str) -> Result<u64, String> {
+    let value: u64 = minutes.parse().map_err(|_| "Invalid idle timeout")?;
+
+    if value <= 1440 {
+        Ok(value)
+    } else {
+        Err("Idle timeout must be between 0 and 1440 minutes".to_string())
+    }
+}
+
+pub fn parse_forward(ports: &str) -> Result<PortForward, String> {
+    let parse = |port: &str| match port.parse::<u16>() {
+        Ok(port) if port > 0 => Ok(port),
+        _ => Err(format!("Invalid port {}", port)),
+    };
+    match ports.split_once(':') {
+        Some((remote, local)) => Ok(PortForward {
+            remote: parse(remote)?,
+            local: parse(local)?,
+        }),
+        None => {
+            let port = parse(ports)?;
+            Ok(PortForward {
+                remote: port,
+                local: port,
+            })
+        }
+    }
+}
+
+pub fn parse_month(month: &str) -> Result<String, String> {
+    match chrono::NaiveDate::parse_from_str(&format!("{}-01", month), "%Y-%m-%d") {
+        Ok(_) => Ok(month.to_string()),
+        Err(_) => Err("Month must be formatted as YYYY-MM".to_string()),
+    }
+}
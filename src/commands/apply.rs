@@ -0,0 +1,101 @@
+use crate::cli::io;
+use crate::cli::progress::Progress;
+use crate::utils::{
+    client::FlyClient,
+    error::SpsdError,
+    machines::{
+        create_machine, ensure_ports_are_unique, extend_volume, get_instances_with_config,
+        update_instance_specs,
+    },
+    manifest::{load_manifest, plan, DesiredInstance, PlanAction, PlanEntry},
+    types::{Instance, MachineConfig, OutputFormat},
+};
+use std::path::Path;
+
+pub async fn apply_manifest(
+    client: &FlyClient,
+    path: &Path,
+    dry_run: bool,
+    format: OutputFormat,
+) -> Result<String, SpsdError> {
+    let desired = load_manifest(path)?;
+    let current = get_instances_with_config(client).await?;
+    let entries = plan(&desired, &current);
+    // other formats get the plan once it has been applied
+    if format == OutputFormat::Table {
+        io::display_plan(&entries);
+    }
+
+    let pending = entries
+        .iter()
+        .filter(|entry| entry.action != PlanAction::Unchanged)
+        .count();
+    if pending > 0 && !dry_run {
+        let progress = match format {
+            OutputFormat::Table => Progress::new(),
+            _ => Progress::hidden(),
+        };
+        apply_entries(client, &entries, &desired, current, &progress).await?;
+    }
+
+    match format {
+        OutputFormat::Table if pending == 0 => Ok(String::from("Instances are up to date")),
+        OutputFormat::Table if dry_run => Ok(format!("{} instance(s) would change", pending)),
+        OutputFormat::Table => Ok(format!("Applied changes to {} instance(s)", pending)),
+        format => {
+            io::display_data(&entries, format, || {})?;
+            Ok(String::new())
+        }
+    }
+}
+
+async fn apply_entries(
+    client: &FlyClient,
+    entries: &[PlanEntry],
+    desired: &[DesiredInstance],
+    current: Vec<(Instance, MachineConfig)>,
+    progress: &Progress,
+) -> Result<(), SpsdError> {
+    let instances: Vec<Instance> = current.into_iter().map(|(instance, _)| instance).collect();
+    for (entry, desired) in entries.iter().zip(desired) {
+        match entry.action {
+            PlanAction::Create => {
+                create_machine(
+                    client,
+                    &desired.name,
+                    &desired.image,
+                    desired.specs.clone(),
+                    &desired.region,
+                    &desired.ports,
+                    &desired.options,
+                    progress,
+                    true,
+                )
+                .await?;
+            }
+            PlanAction::Update => {
+                let instance = instances
+                    .iter()
+                    .find(|instance| instance.name == desired.name)
+                    .unwrap();
+                if instance.ports != desired.ports {
+                    ensure_ports_are_unique(&instances, &desired.ports, Some(&desired.name))?;
+                }
+                if desired.specs.volume_gb > instance.specs.volume_gb {
+                    extend_volume(client, instance, desired.specs.volume_gb).await?;
+                }
+                update_instance_specs(
+                    client,
+                    instance,
+                    &desired.image,
+                    &desired.specs,
+                    &desired.ports,
+                    Some(&desired.options),
+                )
+                .await?;
+            }
+            PlanAction::Unchanged => {}
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,44 @@
+use crate::cli::io;
+use crate::cli::progress::Progress;
+use crate::utils::{
+    client::FlyClient,
+    error::SpsdError,
+    machines::{clone_machine, get_instances_with_config},
+    types::{MachineOptions, OutputFormat, PortSpec},
+};
+
+pub async fn clone_instance(
+    client: &FlyClient,
+    source: &str,
+    name: &str,
+    region: Option<&str>,
+    ports: &[PortSpec],
+    format: OutputFormat,
+) -> Result<String, SpsdError> {
+    let (instance, config) = get_instances_with_config(client)
+        .await?
+        .into_iter()
+        .find(|(instance, _)| instance.name == source)
+        .ok_or_else(|| SpsdError::NotFound(format!("Instance {} not found", source)))?;
+
+    let region = region.unwrap_or(&instance.region);
+    let options = MachineOptions::from_config(&config);
+    let progress = match format {
+        OutputFormat::Table => Progress::new(),
+        _ => Progress::hidden(),
+    };
+    let clone = clone_machine(client, &instance, &options, name, region, ports, &progress).await?;
+    if format != OutputFormat::Table {
+        io::display_data(&clone, format, || {})?;
+        return Ok(String::new());
+    }
+
+    // ports have to be unique across instances, so the source's ports aren't carried over
+    if ports.is_empty() && !instance.ports.is_empty() {
+        return Ok(format!(
+            "Cloned instance {} into {} without ports, use `spsd resize {} --port <port>` to expose them",
+            source, name, name
+        ));
+    }
+    Ok(format!("Cloned instance {} into {}", source, name))
+}
@@ -0,0 +1,108 @@
+use super::start::{start_if_stopped, stop_if_started};
+use crate::utils::{
+    client::FlyClient,
+    connector::{Connector, Direction, Transfer},
+    error::SpsdError,
+    machines::get_instance,
+};
+use std::path::Path;
+
+// relative paths on the instance are resolved against the persistent volume
+const REMOTE_BASE: &str = "/data";
+
+pub async fn copy(
+    client: &FlyClient,
+    connector: &dyn Connector,
+    source: &str,
+    destination: &str,
+    recursive: bool,
+) -> Result<String, SpsdError> {
+    let (name, transfer) =
+        match (parse_remote(source), parse_remote(destination)) {
+            (None, Some((name, remote))) => (
+                name,
+                Transfer {
+                    direction: Direction::Upload,
+                    local: source.to_string(),
+                    remote,
+                    recursive,
+                },
+            ),
+            (Some((name, remote)), None) => (
+                name,
+                Transfer {
+                    direction: Direction::Download,
+                    local: destination.to_string(),
+                    remote,
+                    recursive,
+                },
+            ),
+            _ => return Err(SpsdError::Validation(String::from(
+                "Exactly one of source and destination must be an instance path (<name>:<path>)",
+            ))),
+        };
+
+    if matches!(transfer.direction, Direction::Upload)
+        && !recursive
+        && Path::new(&transfer.local).is_dir()
+    {
+        return Err(SpsdError::Validation(format!(
+            "{} is a directory, pass -r to copy it",
+            transfer.local
+        )));
+    }
+
+    let mut instance = get_instance(client, name).await?;
+    let mut command = connector.copy(&instance, &transfer)?;
+    let started = start_if_stopped(client, &mut instance).await?;
+
+    let result = match command.spawn() {
+        Ok(mut child) => child.wait().await.map_err(SpsdError::from),
+        Err(error) => Err(connector.spawn_error(&command, error)),
+    };
+    // the instance is stopped again even if the copy failed
+    match stop_if_started(client, &instance, started, result).await? {
+        status if status.success() => Ok(format!("Copied {} to {}", source, destination)),
+        _ => Err(SpsdError::CommandFailed(format!(
+            "Copying {} to {} failed",
+            source, destination
+        ))),
+    }
+}
+
+// splits `<name>:<path>` into the instance name and an absolute path on the instance
+fn parse_remote(location: &str) -> Option<(&str, String)> {
+    let (name, path) = location.split_once(':')?;
+    if name.is_empty() || name.contains('/') {
+        return None;
+    }
+    let path = match path {
+        "" => REMOTE_BASE.to_string(),
+        path if path.starts_with('/') => path.to_string(),
+        path => format!("{}/{}", REMOTE_BASE, path),
+    };
+    Some((name, path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn remote_paths_are_resolved_against_the_volume() {
+        assert_eq!(
+            parse_remote("devbox:notes.txt"),
+            Some(("devbox", String::from("/data/notes.txt")))
+        );
+        assert_eq!(
+            parse_remote("devbox:/etc/hosts"),
+            Some(("devbox", String::from("/etc/hosts")))
+        );
+        assert_eq!(
+            parse_remote("devbox:"),
+            Some(("devbox", String::from("/data")))
+        );
+        assert_eq!(parse_remote("./notes.txt"), None);
+        assert_eq!(parse_remote("dir/a:b"), None);
+    }
+}
@@ -1,10 +1,16 @@
-use std::error::Error;
+use super::start::record_usage;
+use crate::utils::{
+    client::FlyClient,
+    error::SpsdError,
+    machines::{delete_machine, get_instance},
+    usage::UsageEventKind,
+};
 
-use crate::utils;
-pub fn destroy_instance(name: &str) -> Result<String, Box<dyn Error>> {
-    let result = utils::machines::delete_machine(name);
-    match result {
-        Ok(_) => Ok(String::from(format!("Destroyed instance {}", name))),
-        Err(contents) => Err(contents),
+pub async fn destroy_instance(client: &FlyClient, name: &str) -> Result<String, SpsdError> {
+    let instance = get_instance(client, name).await?;
+    delete_machine(client, &instance).await?;
+    if instance.state.is_running() {
+        record_usage(&instance, UsageEventKind::Stop);
     }
+    Ok(format!("Destroyed instance {}", name))
 }
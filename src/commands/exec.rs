@@ -0,0 +1,123 @@
+use super::start::{start_if_stopped, stop_if_started};
+use crate::utils::{
+    client::FlyClient, connector::Connector, error::SpsdError, machines::get_instance,
+};
+use std::time::Duration;
+use tokio::time::timeout;
+
+// runs `command` over the connector so its output is streamed while it runs, which fly's exec
+// endpoint can't do. an instance started for it is stopped again, a running one only with `stop`
+pub async fn exec_command(
+    client: &FlyClient,
+    connector: &dyn Connector,
+    name: &str,
+    command: &[String],
+    timeout_secs: u64,
+    stop: bool,
+) -> Result<String, SpsdError> {
+    let mut instance = get_instance(client, name).await?;
+    let started = start_if_stopped(client, &mut instance).await?;
+    let mut remote = connector.exec(&instance, command);
+    let result = match remote.spawn() {
+        Ok(mut child) => match timeout(Duration::from_secs(timeout_secs), child.wait()).await {
+            Ok(status) => status.map_err(SpsdError::from),
+            Err(_) => {
+                child.kill().await?;
+                Err(SpsdError::CommandFailed(format!(
+                    "Command did not finish within {} seconds",
+                    timeout_secs
+                )))
+            }
+        },
+        Err(error) => Err(connector.spawn_error(&remote, error)),
+    };
+    let status = stop_if_started(client, &instance, started || stop, result).await?;
+
+    match status.code() {
+        Some(0) => Ok(String::new()),
+        Some(code) => Err(SpsdError::RemoteExit(code)),
+        None => Err(SpsdError::CommandFailed(String::from(
+            "Command was terminated by a signal",
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::machines::start_machine;
+    use crate::utils::mock_server::{
+        create_test_instance, LocalConnector, MockServer, TestInstance,
+    };
+
+    fn command(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[tokio::test]
+    async fn exit_code_is_passed_on_and_instance_stopped_again() {
+        let server = MockServer::start();
+        let client = server.client();
+        create_test_instance(&client, "devbox", TestInstance::default())
+            .await
+            .unwrap();
+
+        let result = exec_command(
+            &client,
+            &LocalConnector,
+            "devbox",
+            &command(&["exit", "3"]),
+            10,
+            true,
+        )
+        .await;
+        assert!(matches!(result, Err(SpsdError::RemoteExit(3))));
+        assert_eq!(server.machines()[0]["state"], "stopped");
+
+        let result = exec_command(
+            &client,
+            &LocalConnector,
+            "devbox",
+            &command(&["sleep", "5"]),
+            1,
+            true,
+        )
+        .await;
+        assert!(matches!(result, Err(SpsdError::CommandFailed(_))));
+    }
+
+    #[tokio::test]
+    async fn only_instances_started_for_the_command_are_stopped_without_stop() {
+        let server = MockServer::start();
+        let client = server.client();
+        create_test_instance(&client, "devbox", TestInstance::default())
+            .await
+            .unwrap();
+
+        exec_command(
+            &client,
+            &LocalConnector,
+            "devbox",
+            &command(&["true"]),
+            10,
+            false,
+        )
+        .await
+        .unwrap();
+        assert_eq!(server.machines()[0]["state"], "stopped");
+
+        let instance = get_instance(&client, "devbox").await.unwrap();
+        start_machine(&client, &instance).await.unwrap();
+        exec_command(
+            &client,
+            &LocalConnector,
+            "devbox",
+            &command(&["true"]),
+            10,
+            false,
+        )
+        .await
+        .unwrap();
+        assert_eq!(server.machines()[0]["state"], "started");
+    }
+}
@@ -0,0 +1,38 @@
+use super::start::{start_if_stopped, stop_if_started};
+use crate::cli::io::display_message;
+use crate::utils::{
+    client::FlyClient, connector::Connector, error::SpsdError, machines::get_instance,
+    types::PortForward,
+};
+
+pub async fn forward_port(
+    client: &FlyClient,
+    connector: &dyn Connector,
+    name: &str,
+    ports: PortForward,
+) -> Result<String, SpsdError> {
+    let mut instance = get_instance(client, name).await?;
+    let mut command = connector.forward(&instance, ports);
+    let started = start_if_stopped(client, &mut instance).await?;
+
+    let result = match command.spawn() {
+        Ok(mut child) => {
+            display_message(&format!(
+                "Forwarding localhost:{} to port {} on {}, press Ctrl-C to stop",
+                ports.local, ports.remote, name
+            ));
+            // ctrl-c reaches the tunnel too, but spsd has to outlive it to stop the instance
+            tokio::select! {
+                status = child.wait() => status.map(|_| ()).map_err(SpsdError::from),
+                _ = tokio::signal::ctrl_c() => child.kill().await.map_err(SpsdError::from),
+            }
+        }
+        Err(error) => Err(connector.spawn_error(&command, error)),
+    };
+    stop_if_started(client, &instance, started, result).await?;
+
+    Ok(format!(
+        "Stopped forwarding port {} on {}",
+        ports.remote, name
+    ))
+}
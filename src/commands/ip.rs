@@ -0,0 +1,70 @@
+use crate::cli::io;
+use crate::utils::client::FlyClient;
+use crate::utils::error::SpsdError;
+use crate::utils::types::{IpType, OutputFormat};
+
+pub async fn list_ips(client: &FlyClient, format: OutputFormat) -> Result<String, SpsdError> {
+    let addresses = client.list_ip_addresses().await?;
+    io::display_ips(addresses, format)?;
+    Ok(String::new())
+}
+
+pub async fn allocate_ip(
+    client: &FlyClient,
+    ip_type: IpType,
+    region: Option<&str>,
+) -> Result<String, SpsdError> {
+    let address = match client.allocate_ip_address(ip_type, region).await? {
+        Some(address) => address,
+        None => client
+            .list_ip_addresses()
+            .await?
+            .into_iter()
+            .find(|address| address.ip_type == ip_type)
+            .ok_or_else(|| SpsdError::CommandFailed(String::from("Allocated address not found")))?,
+    };
+    Ok(format!("Allocated {}", address.address))
+}
+
+pub async fn release_ip(client: &FlyClient, address: &str) -> Result<String, SpsdError> {
+    if !client
+        .list_ip_addresses()
+        .await?
+        .iter()
+        .any(|allocated| allocated.address == address)
+    {
+        return Err(SpsdError::NotFound(format!(
+            "Address {} is not allocated to {}",
+            address,
+            client.app_name()
+        )));
+    }
+    client.release_ip_address(address).await?;
+    Ok(format!("Released {}", address))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::mock_server::MockServer;
+
+    #[tokio::test]
+    async fn allocate_and_release_addresses() {
+        let server = MockServer::start();
+        let client = server.client();
+
+        allocate_ip(&client, IpType::V4, None).await.unwrap();
+        let message = allocate_ip(&client, IpType::SharedV4, None).await.unwrap();
+        let addresses = client.list_ip_addresses().await.unwrap();
+        assert_eq!(addresses.len(), 2);
+        assert!(message.ends_with(&addresses[1].address));
+
+        let v4 = addresses[0].address.clone();
+        release_ip(&client, &v4).await.unwrap();
+        assert_eq!(client.list_ip_addresses().await.unwrap().len(), 1);
+        assert!(matches!(
+            release_ip(&client, &v4).await,
+            Err(SpsdError::NotFound(_))
+        ));
+    }
+}
@@ -1,22 +1,28 @@
 use crate::cli::io;
-use crate::utils::config::get_app_name;
+use crate::commands::ip::list_ips;
+use crate::utils::client::FlyClient;
+use crate::utils::error::SpsdError;
 use crate::utils::machines::get_instances;
-use std::error::Error;
-use std::process::Command;
+use crate::utils::types::OutputFormat;
 
-pub fn list_instances(list_ips: bool) -> Result<String, Box<dyn Error>> {
-    if list_ips {
-        let mut child = Command::new("flyctl")
-            .arg("ip")
-            .arg("list")
-            .arg("-a")
-            .arg(get_app_name()?)
-            .spawn()?;
-        child.wait().unwrap();
-    } else {
-        let result = get_instances()?;
-        io::display_instances(result);
+pub async fn list_instances(
+    client: &FlyClient,
+    list_ips_only: bool,
+    format: OutputFormat,
+) -> Result<String, SpsdError> {
+    if list_ips_only {
+        return list_ips(client, format).await;
     }
 
+    let instances = get_instances(client).await?;
+    // addresses only add detail to the table, so failing to get them doesn't fail the listing
+    let addresses = match client.list_ip_addresses().await {
+        Ok(addresses) => addresses,
+        Err(error) => {
+            io::display_warning(&format!("Could not list IP addresses: {}", error));
+            Vec::new()
+        }
+    };
+    io::display_instances(instances, &addresses, format)?;
     Ok(String::new())
 }
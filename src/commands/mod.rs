@@ -1,7 +1,18 @@
+pub mod apply;
+pub mod clone;
+pub mod cp;
 pub mod destroy;
+pub mod exec;
+pub mod forward;
+pub mod ip;
 pub mod list;
 pub mod new;
 pub mod profile;
+pub mod resize;
+pub mod secrets;
 pub mod sl;
+pub mod snapshot;
 pub mod start;
 pub mod stop;
+pub mod usage;
+pub mod wait;
@@ -1,19 +1,50 @@
-use std::error::Error;
+use crate::cli::io;
+use crate::cli::progress::Progress;
+use crate::utils::{
+    self,
+    client::FlyClient,
+    error::SpsdError,
+    types::{InstanceSpecs, MachineOptions, OutputFormat, PortSpec},
+};
 
-use crate::utils;
-
-pub fn create_new_instance<'a>(
+#[allow(clippy::too_many_arguments)]
+pub async fn create_new_instance(
+    client: &FlyClient,
     name: &str,
     image: &str,
     cpus: u32,
     memory: u32,
     volume: u32,
     region: &str,
-    port: Option<u16>,
-) -> Result<String, Box<dyn Error>> {
-    let result = utils::machines::create_machine(name, image, cpus, memory, volume, region, port);
-    match result {
-        Ok(_) => Ok(String::from(format!("Created instance {}", name))),
-        Err(contents) => Err(contents),
+    ports: &[PortSpec],
+    options: &MachineOptions,
+    wait: bool,
+    format: OutputFormat,
+) -> Result<String, SpsdError> {
+    let specs = InstanceSpecs {
+        cpu_kind: String::from("shared"),
+        cpu_count: cpus,
+        memory_mb: memory,
+        volume_gb: volume,
+    };
+    // machine readable output is meant for scripts, which don't need spinners either
+    let progress = match format {
+        OutputFormat::Table => Progress::new(),
+        _ => Progress::hidden(),
+    };
+    let instance = utils::machines::create_machine(
+        client, name, image, specs, region, ports, options, &progress, wait,
+    )
+    .await?;
+    match format {
+        OutputFormat::Table if !wait => Ok(format!(
+            "Created instance {} without starting it, its image is pulled when it first starts",
+            name
+        )),
+        OutputFormat::Table => Ok(format!("Created instance {}", name)),
+        format => {
+            io::display_data(&instance, format, || {})?;
+            Ok(String::new())
+        }
     }
 }
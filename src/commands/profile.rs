@@ -1,69 +1,181 @@
-use std::error::Error;
-use std::process::Command;
-use std::str;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::path::Path;
 
-use crate::utils::{
-    config::{get_app_name, write_config},
-    types::Config,
+use crate::cli::io;
+use crate::utils::client::FlyClient;
+use crate::utils::config::{
+    active_profile_name, api_url_for, graphql_url_for, read_config_file, read_config_file_at,
+    write_config_file, write_config_file_at,
 };
+use crate::utils::error::SpsdError;
+use crate::utils::types::{Config, CredentialStore, IpType, OutputFormat, ProfileSummary};
 
-pub fn modify_profile(api_key: &str, allocate_ip: bool) -> Result<String, Box<dyn Error>> {
-    let output = Command::new("flyctl").arg("auth").arg("login").output()?;
+const DEFAULT_ORG: &str = "personal";
 
-    if !output.status.success() {
-        return Err(format!(
-            "Authentication failed: {}",
-            String::from_utf8_lossy(&output.stderr)
-        )
-        .into());
+// creates the profile or updates the settings it already has. the api key is checked against
+// fly before anything is saved, and the profile's app is created if it doesn't exist yet. the
+// profile is saved to `config_path`, and its api key with `store_api_key`
+pub async fn add_profile(
+    name: &str,
+    api_key: &str,
+    allocate_ip: bool,
+    settings: Config,
+    config_path: &Path,
+    store_api_key: fn(&str, &str) -> Result<CredentialStore, SpsdError>,
+) -> Result<String, SpsdError> {
+    let mut config_file = read_config_file_at(config_path)?;
+    let mut config = config_file.profiles.remove(name).unwrap_or_default();
+    config.merge(settings);
+
+    let app_name = config
+        .fly_app_name
+        .clone()
+        .unwrap_or_else(generate_app_name);
+    // the profile's own urls, since it may not exist yet even if it's the active one
+    let client = FlyClient::new(
+        &api_url_for(&config),
+        &graphql_url_for(&config),
+        &app_name,
+        api_key,
+    )?;
+    client.get_viewer().await?;
+    let created = ensure_app(&client, config.fly_org.as_deref()).await?;
+
+    config.fly_app_name = Some(app_name.clone());
+    config.fly_api_key = None;
+    config.credential = Some(store_api_key(name, api_key)?);
+    config_file.profiles.insert(name.to_string(), config);
+    write_config_file_at(config_path, &config_file)?;
+
+    if allocate_ip {
+        client.allocate_ip_address(IpType::V4, None).await?;
     }
 
-    let result = get_app_name();
-    let app_name = match result {
-        Ok(app_name) => app_name,
-        Err(_) => {
-            let output = Command::new("flyctl")
-                .arg("apps")
-                .arg("create")
-                .arg("--generate-name")
-                .output()?;
-
-            if !output.status.success() {
-                return Err(format!(
-                    "App creation failed: {}",
-                    String::from_utf8_lossy(&output.stderr)
-                )
-                .into());
-            }
-
-            let stdout = String::from_utf8_lossy(&output.stdout);
-            stdout
-                .split('\n')
-                .find(|line| line.contains("New app created:"))
-                .and_then(|line| line.split_whitespace().last())
-                .ok_or("App name not found in output")?
-                .to_string()
+    Ok(format!(
+        "{} app {} for profile {}",
+        if created { "Created" } else { "Using" },
+        app_name,
+        name
+    ))
+}
+
+// creates the client's app in `org`, or adopts it if it already exists. returns whether it was created
+async fn ensure_app(client: &FlyClient, org: Option<&str>) -> Result<bool, SpsdError> {
+    match client.get_app().await {
+        Ok(app) => match org {
+            Some(org) if org != app.organization.slug => Err(SpsdError::Validation(format!(
+                "App {} belongs to the {} organization, not {}",
+                app.name, app.organization.slug, org
+            ))),
+            _ => Ok(false),
+        },
+        Err(SpsdError::NotFound(_)) => {
+            client.create_app(org.unwrap_or(DEFAULT_ORG)).await?;
+            Ok(true)
         }
-    };
+        Err(error) => Err(error),
+    }
+}
+
+fn generate_app_name() -> String {
+    // RandomState is seeded randomly per process, which is plenty for a unique suffix
+    let suffix = RandomState::new().build_hasher().finish();
+    format!("spsd-{:08x}", suffix as u32)
+}
+
+pub fn use_profile(name: &str) -> Result<String, SpsdError> {
+    let mut config_file = read_config_file()?;
+    if !config_file.profiles.contains_key(name) {
+        return Err(SpsdError::ConfigMissing(format!("Profile {}", name)));
+    }
+    config_file.current_profile = Some(name.to_string());
+    write_config_file(&config_file)?;
+    Ok(format!("Switched to profile {}", name))
+}
+
+pub fn list_profiles(format: OutputFormat) -> Result<String, SpsdError> {
+    let config_file = read_config_file()?;
+    let active = active_profile_name(&config_file);
+    let profiles: Vec<ProfileSummary> = config_file
+        .profiles
+        .into_iter()
+        .map(|(name, config)| ProfileSummary {
+            active: name == active,
+            name,
+            app: config.fly_app_name,
+            org: config.fly_org,
+            region: config.region,
+        })
+        .collect();
+    io::display_profiles(profiles, format)?;
+    Ok(String::new())
+}
 
-    let config = Config {
-        fly_api_key: Some(api_key.to_owned()),
-        fly_app_name: Some(app_name),
-    };
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::mock_server::{MockServer, MOCK_API_KEY, MOCK_APP_NAME};
+    use crate::utils::types::ConfigFile;
 
-    write_config(&config)?;
+    #[tokio::test]
+    async fn apps_are_adopted_or_created() {
+        let server = MockServer::start();
 
-    if allocate_ip {
-        let mut child = Command::new("flyctl")
-            .arg("ip")
-            .arg("allocate-v4")
-            .spawn()?;
-        let status = child.wait().unwrap();
-
-        if !status.success() {
-            return Err("IP allocation failed".into());
-        }
+        let existing = server.client_for(MOCK_APP_NAME, MOCK_API_KEY);
+        assert!(!ensure_app(&existing, None).await.unwrap());
+        assert!(matches!(
+            ensure_app(&existing, Some("acme")).await,
+            Err(SpsdError::Validation(_))
+        ));
+
+        let new = server.client_for("spsd-0000beef", MOCK_API_KEY);
+        assert!(ensure_app(&new, Some("acme")).await.unwrap());
+        assert_eq!(new.get_app().await.unwrap().organization.slug, "acme");
+
+        let unauthorized = server.client_for(MOCK_APP_NAME, "fo1_invalid");
+        assert!(matches!(
+            unauthorized.get_viewer().await,
+            Err(SpsdError::Auth(_))
+        ));
     }
 
-    Ok(String::from("Set app and API key"))
+    #[tokio::test]
+    async fn profiles_can_be_added_while_selected() {
+        let server = MockServer::start();
+        let config_path =
+            std::env::temp_dir().join(format!("spsd_profile_config_{}.json", std::process::id()));
+        // "work" is selected before it exists, as `--profile work` would select it
+        let selected = ConfigFile {
+            current_profile: Some(String::from("work")),
+            ..ConfigFile::default()
+        };
+        write_config_file_at(&config_path, &selected).unwrap();
+
+        let settings = Config {
+            fly_app_name: Some(MOCK_APP_NAME.to_string()),
+            fly_api_url: Some(server.url.clone()),
+            fly_graphql_url: Some(server.graphql_url.clone()),
+            ..Config::default()
+        };
+        let message = add_profile(
+            "work",
+            MOCK_API_KEY,
+            false,
+            settings,
+            &config_path,
+            |_, _| Ok(CredentialStore::File),
+        )
+        .await;
+        let config_file = read_config_file_at(&config_path);
+        std::fs::remove_file(&config_path).unwrap();
+
+        assert_eq!(
+            message.unwrap(),
+            format!("Using app {} for profile work", MOCK_APP_NAME)
+        );
+        let profile = &config_file.unwrap().profiles["work"];
+        assert_eq!(profile.fly_app_name.as_deref(), Some(MOCK_APP_NAME));
+        assert_eq!(profile.credential, Some(CredentialStore::File));
+    }
 }
@@ -0,0 +1,94 @@
+use crate::cli::io;
+use crate::utils::{
+    client::FlyClient,
+    error::SpsdError,
+    machines::{
+        ensure_ports_are_unique, extend_volume, get_instances_with_config, update_instance_specs,
+    },
+    types::{MachineOptions, OutputFormat, PortSpec},
+};
+use std::collections::BTreeMap;
+
+#[allow(clippy::too_many_arguments)]
+pub async fn resize_instance(
+    client: &FlyClient,
+    name: &str,
+    image: Option<&str>,
+    cpus: Option<u32>,
+    memory: Option<u32>,
+    volume: Option<u32>,
+    ports: &[PortSpec],
+    env: &BTreeMap<String, String>,
+    unset_env: &[String],
+    format: OutputFormat,
+) -> Result<String, SpsdError> {
+    let updates_env = !env.is_empty() || !unset_env.is_empty();
+    let updates_machine =
+        image.is_some() || cpus.is_some() || memory.is_some() || !ports.is_empty() || updates_env;
+    if !updates_machine && volume.is_none() {
+        return Err(SpsdError::Validation(String::from(
+            "Nothing to change, pass at least one of --image, --cpus, --memory, --volume, --port, --env or --unset-env",
+        )));
+    }
+
+    // one listing serves both to find the instance and to check its new ports against the others
+    let (instances, configs): (Vec<_>, Vec<_>) =
+        get_instances_with_config(client).await?.into_iter().unzip();
+    let index = instances
+        .iter()
+        .position(|instance| instance.name == name)
+        .ok_or_else(|| SpsdError::NotFound(format!("Instance {} not found", name)))?;
+    if !ports.is_empty() && ports != instances[index].ports {
+        ensure_ports_are_unique(&instances, ports, Some(name))?;
+    }
+    let instance = &instances[index];
+    let config = &configs[index];
+
+    // the volume goes first so a rejected shrink doesn't leave the machine half updated
+    let needs_restart = match volume {
+        Some(volume) => extend_volume(client, instance, volume).await?,
+        None => false,
+    };
+
+    let resized = if updates_machine {
+        let mut specs = instance.specs.clone();
+        specs.cpu_count = cpus.unwrap_or(specs.cpu_count);
+        specs.memory_mb = memory.unwrap_or(specs.memory_mb);
+        // env is merged into what the machine already has rather than replacing it
+        let mut options = MachineOptions::from_config(config);
+        options.env.extend(env.clone());
+        for key in unset_env {
+            options.env.remove(key);
+        }
+        update_instance_specs(
+            client,
+            instance,
+            image.unwrap_or(&instance.image),
+            &specs,
+            if ports.is_empty() {
+                &instance.ports
+            } else {
+                ports
+            },
+            updates_env.then_some(&options),
+        )
+        .await?
+    } else {
+        let mut resized = instance.clone();
+        resized.specs.volume_gb = volume.unwrap_or(resized.specs.volume_gb);
+        resized
+    };
+
+    if format != OutputFormat::Table {
+        io::display_data(&resized, format, || {})?;
+        return Ok(String::new());
+    }
+    if !updates_machine && needs_restart && instance.state.is_running() {
+        return Ok(format!(
+            "Resized instance {}, restart it to use the larger volume",
+            name
+        ));
+    }
+
+    Ok(format!("Resized instance {}", name))
+}
@@ -0,0 +1,118 @@
+use crate::cli::io;
+use crate::utils::{
+    client::FlyClient,
+    error::SpsdError,
+    machines::{get_instances, update_machine_config},
+    types::OutputFormat,
+};
+use std::collections::BTreeMap;
+
+pub async fn list_secrets(client: &FlyClient, format: OutputFormat) -> Result<String, SpsdError> {
+    io::display_secrets(client.list_secrets().await?, format)?;
+    Ok(String::new())
+}
+
+pub async fn set_secrets(
+    client: &FlyClient,
+    secrets: &BTreeMap<String, String>,
+    stage: bool,
+) -> Result<String, SpsdError> {
+    client.set_secrets(secrets).await?;
+    let names: Vec<&str> = secrets.keys().map(String::as_str).collect();
+    finish(client, &format!("Set {}", names.join(", ")), stage).await
+}
+
+pub async fn unset_secrets(
+    client: &FlyClient,
+    keys: &[String],
+    stage: bool,
+) -> Result<String, SpsdError> {
+    client.unset_secrets(keys).await?;
+    finish(client, &format!("Unset {}", keys.join(", ")), stage).await
+}
+
+// machines only read secrets when their config is updated, so every instance gets an update
+// unless the change is staged. running instances restart to pick the secrets up. one instance
+// failing to update doesn't keep the others from getting the change
+async fn finish(client: &FlyClient, message: &str, stage: bool) -> Result<String, SpsdError> {
+    if stage {
+        return Ok(format!(
+            "{}, instances get the change the next time they're updated",
+            message
+        ));
+    }
+    let instances = get_instances(client).await?;
+    let mut updated = Vec::new();
+    let mut failed = Vec::new();
+    for instance in &instances {
+        match update_machine_config(client, instance, |_| {}).await {
+            Ok(_) => updated.push(instance.name.as_str()),
+            Err(error) => failed.push(format!("{} ({})", instance.name, error)),
+        }
+    }
+
+    let updated = match updated.is_empty() {
+        true => String::from("no instances"),
+        false => updated.join(", "),
+    };
+    if !failed.is_empty() {
+        return Err(SpsdError::CommandFailed(format!(
+            "{} and updated {}, but could not update {}. Update them to pick up the change",
+            message,
+            updated,
+            failed.join(", ")
+        )));
+    }
+    Ok(format!("{} and updated {}", message, updated))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::mock_server::{create_test_instance, MockServer, TestInstance, MOCK_API_KEY};
+
+    #[tokio::test]
+    async fn secrets_are_set_listed_and_unset() {
+        let server = MockServer::start();
+        let client = server.client();
+        let secrets = BTreeMap::from([
+            (String::from("API_TOKEN"), String::from("s3cr3t")),
+            (String::from("DB_URL"), String::from("postgres://")),
+        ]);
+
+        let message = set_secrets(&client, &secrets, false).await.unwrap();
+        assert!(!message.contains("s3cr3t"));
+        assert_eq!(server.secrets(), secrets);
+
+        unset_secrets(&client, &[String::from("DB_URL")], true)
+            .await
+            .unwrap();
+        let listed = client.list_secrets().await.unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].name, "API_TOKEN");
+
+        let other_app = server.client_for("other", MOCK_API_KEY);
+        let error = other_app.list_secrets().await.unwrap_err();
+        assert!(matches!(error, SpsdError::NotFound(_)));
+    }
+
+    #[tokio::test]
+    async fn instances_that_fail_to_update_are_reported() {
+        let server = MockServer::start();
+        let client = server.client();
+        for name in ["first", "second"] {
+            create_test_instance(&client, name, TestInstance::default())
+                .await
+                .unwrap();
+        }
+        let secrets = BTreeMap::from([(String::from("API_TOKEN"), String::from("s3cr3t"))]);
+
+        let first_id = server.machines()[0]["id"].as_str().unwrap().to_string();
+        server.fail_next("POST", &first_id, 500);
+        let error = set_secrets(&client, &secrets, false).await.unwrap_err();
+        let message = error.to_string();
+        assert!(message.contains("updated second"));
+        assert!(message.contains("could not update first"));
+        assert_eq!(server.secrets(), secrets);
+    }
+}
@@ -1,13 +1,86 @@
-use super::{start::start_instance, stop::stop_instance};
-use std::{error::Error, time::Instant};
+use super::start::{connect, record_usage};
+use crate::cli::io::{display_warning, format_duration};
+use crate::utils::{
+    client::FlyClient,
+    connector::Connector,
+    error::SpsdError,
+    machines::{
+        get_instance, remove_idle_timeout, start_machine, start_machine_with_idle_timeout,
+        stop_machine,
+    },
+    types::InstanceState,
+    usage::UsageEventKind,
+};
+use std::time::Instant;
 
-pub fn serverless(name: &str) -> Result<String, Box<dyn Error>> {
+pub async fn serverless(
+    client: &FlyClient,
+    connector: &dyn Connector,
+    name: &str,
+    idle_timeout_mins: u64,
+) -> Result<String, SpsdError> {
     let start_time = Instant::now();
 
-    start_instance(name)?;
-    stop_instance(name)?;
+    let mut instance = get_instance(client, name).await?;
+    let started = if idle_timeout_mins > 0 {
+        start_machine_with_idle_timeout(client, &instance, idle_timeout_mins * 60).await?
+    } else {
+        start_machine(client, &instance).await?
+    };
+    if started {
+        record_usage(&instance, UsageEventKind::Start);
+    } else if idle_timeout_mins > 0 {
+        // the watchdog only takes effect on a restart, which would cut off whoever is using it
+        display_warning(&format!(
+            "Instance {} is already running, so the idle timeout was not applied",
+            name
+        ));
+    }
+    let watchdog_installed = started && idle_timeout_mins > 0;
+    instance.state = InstanceState::Started;
+    connect(connector, &instance).await?;
+    stop_machine(client, &instance).await?;
+    record_usage(&instance, UsageEventKind::Stop);
+    if watchdog_installed {
+        remove_idle_timeout(client, &instance).await?;
+    }
 
-    let _duration = start_time.elapsed();
+    let duration = start_time.elapsed();
 
-    Ok(String::new())
+    Ok(format!(
+        "Stopped instance {} after {}",
+        name,
+        format_duration(duration)
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::mock_server::{
+        create_test_instance, LocalConnector, MockServer, TestInstance,
+    };
+
+    #[tokio::test]
+    async fn running_instances_are_left_without_a_watchdog() {
+        let server = MockServer::start();
+        let client = server.client();
+        let instance = create_test_instance(&client, "devbox", TestInstance::default())
+            .await
+            .unwrap();
+        start_machine(&client, &instance).await.unwrap();
+        // any update to the machine's config would fail
+        server.fail_next("POST", &instance.machine_id, 500);
+
+        serverless(&client, &LocalConnector, "devbox", 10)
+            .await
+            .unwrap();
+        let machine = &server.machines()[0];
+        assert_eq!(machine["state"], "stopped");
+        assert!(machine["config"]["init"].get("entrypoint").is_none());
+        assert_eq!(
+            machine["config"]["init"]["exec"],
+            serde_json::json!(["/bin/sleep", "inf"])
+        );
+    }
 }
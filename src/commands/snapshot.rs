@@ -0,0 +1,47 @@
+use crate::cli::io;
+use crate::utils::{
+    client::FlyClient, error::SpsdError, machines::get_instance, snapshots, types::OutputFormat,
+};
+
+pub async fn create_snapshot(client: &FlyClient, name: &str) -> Result<String, SpsdError> {
+    let instance = get_instance(client, name).await?;
+    snapshots::create_snapshot(client, &instance).await?;
+    Ok(format!("Started a snapshot of instance {}", name))
+}
+
+pub async fn list_snapshots(
+    client: &FlyClient,
+    name: &str,
+    format: OutputFormat,
+) -> Result<String, SpsdError> {
+    let instance = get_instance(client, name).await?;
+    let snapshots = snapshots::list_snapshots(client, &instance).await?;
+    io::display_snapshots(snapshots, format)?;
+    Ok(String::new())
+}
+
+pub async fn restore_snapshot(
+    client: &FlyClient,
+    name: &str,
+    snapshot_id: Option<&str>,
+    delete_old: bool,
+) -> Result<String, SpsdError> {
+    let instance = get_instance(client, name).await?;
+    let old_volume_id = snapshots::restore_snapshot(client, &instance, snapshot_id).await?;
+
+    // the previous volume holds the data and snapshots from before the restore, so it's only
+    // deleted when asked to
+    if !delete_old {
+        return Ok(format!(
+            "Restored instance {}, the previous volume {} was kept",
+            name, old_volume_id
+        ));
+    }
+    if let Err(error) = client.delete_volume(&old_volume_id).await {
+        io::display_warning(&format!(
+            "Could not delete the previous volume {}: {}",
+            old_volume_id, error
+        ));
+    }
+    Ok(format!("Restored instance {}", name))
+}
@@ -1,17 +1,77 @@
-use crate::utils::{self, config::get_app_name};
-use std::{error::Error, process::Command};
+use crate::cli::io::{display_success, display_warning};
+use crate::utils::{
+    client::FlyClient,
+    connector::Connector,
+    error::SpsdError,
+    machines::{get_instance, start_machine, stop_machine},
+    types::{Instance, InstanceState},
+    usage::{record_event, UsageEventKind},
+};
 
-pub fn start_instance(name: &str) -> Result<String, Box<dyn Error>> {
-    let instance_id = utils::machines::start_machine(name)?;
-    let mut child = Command::new("flyctl")
-        .arg("ssh")
-        .arg("console")
-        .arg("--machine")
-        .arg(instance_id)
-        .arg("--quiet")
-        .arg("-a")
-        .arg(get_app_name()?)
-        .spawn()?;
-    child.wait().unwrap();
+pub async fn start_instance(
+    client: &FlyClient,
+    connector: &dyn Connector,
+    name: &str,
+) -> Result<String, SpsdError> {
+    let instance = get_instance(client, name).await?;
+    if start_machine(client, &instance).await? {
+        record_usage(&instance, UsageEventKind::Start);
+    } else {
+        display_success(&format!("Instance {} is already started", name));
+    }
+    connect(connector, &instance).await?;
     Ok(String::new())
 }
+
+// usage tracking is best effort and never fails the command it's recorded from
+pub fn record_usage(instance: &Instance, kind: UsageEventKind) {
+    if let Err(error) = record_event(instance, kind) {
+        display_warning(&format!("Could not record usage: {}", error));
+    }
+}
+
+pub async fn connect(connector: &dyn Connector, instance: &Instance) -> Result<(), SpsdError> {
+    let mut command = connector.console(instance);
+    let mut child = command
+        .spawn()
+        .map_err(|error| connector.spawn_error(&command, error))?;
+    child.wait().await?;
+    Ok(())
+}
+
+// starts a stopped instance for a one-off command, returning whether it has to be stopped again
+pub async fn start_if_stopped(
+    client: &FlyClient,
+    instance: &mut Instance,
+) -> Result<bool, SpsdError> {
+    if instance.state.is_running() {
+        return Ok(false);
+    }
+    start_machine(client, instance).await?;
+    instance.state = InstanceState::Started;
+    record_usage(instance, UsageEventKind::Start);
+    Ok(true)
+}
+
+// stops the instance again after a one-off command, passing on the command's `result`. if that
+// already failed, a failure to stop is only warned about so it doesn't hide the original error
+pub async fn stop_if_started<T>(
+    client: &FlyClient,
+    instance: &Instance,
+    started: bool,
+    result: Result<T, SpsdError>,
+) -> Result<T, SpsdError> {
+    if !started {
+        return result;
+    }
+    match stop_machine(client, instance).await {
+        Ok(true) => record_usage(instance, UsageEventKind::Stop),
+        Ok(false) => {}
+        Err(error) if result.is_ok() => return Err(error),
+        Err(error) => display_warning(&format!(
+            "Could not stop instance {}: {}",
+            instance.name, error
+        )),
+    }
+    result
+}
@@ -1,11 +1,16 @@
-use std::error::Error;
+use super::start::record_usage;
+use crate::utils::{
+    client::FlyClient,
+    error::SpsdError,
+    machines::{get_instance, stop_machine},
+    usage::UsageEventKind,
+};
 
-use crate::utils::machines::stop_machine;
-
-pub fn stop_instance(name: &str) -> Result<String, Box<dyn Error>> {
-    let result = stop_machine(name);
-    match result {
-        Ok(_) => Ok(String::from(format!("Stopped instance {}", name))),
-        Err(contents) => Err(contents),
+pub async fn stop_instance(client: &FlyClient, name: &str) -> Result<String, SpsdError> {
+    let instance = get_instance(client, name).await?;
+    if !stop_machine(client, &instance).await? {
+        return Ok(format!("Instance {} is already {}", name, instance.state));
     }
+    record_usage(&instance, UsageEventKind::Stop);
+    Ok(format!("Stopped instance {}", name))
 }
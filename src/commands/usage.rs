@@ -0,0 +1,55 @@
+use crate::cli::io::{self, display_warning};
+use crate::utils::client::FlyClient;
+use crate::utils::error::SpsdError;
+use crate::utils::types::{parse_state, OutputFormat};
+use crate::utils::usage::{read_events, reconcile, summarize, MachineActivity};
+use chrono::Utc;
+
+pub async fn show_usage(
+    client: Option<&FlyClient>,
+    name: Option<&str>,
+    month: Option<&str>,
+    format: OutputFormat,
+) -> Result<String, SpsdError> {
+    let mut events = read_events()?;
+    // sessions that were never stopped through spsd would otherwise count until now
+    if let Some(client) = client {
+        match client.list_machines().await {
+            Ok(machines) => {
+                let machines: Vec<MachineActivity> = machines
+                    .iter()
+                    .map(|machine| MachineActivity {
+                        machine_id: machine.id.clone(),
+                        state: parse_state(&machine.state),
+                        updated_at: machine
+                            .updated_at
+                            .as_deref()
+                            .and_then(|updated_at| updated_at.parse().ok()),
+                    })
+                    .collect();
+                for start in reconcile(&mut events, &machines) {
+                    if name.is_none_or(|name| start.instance == name) {
+                        display_warning(&format!(
+                            "Usage of instance {} since {} is not counted, its machine is gone and it's unknown when it stopped",
+                            start.instance,
+                            start.timestamp.format("%Y-%m-%d %H:%M UTC")
+                        ));
+                    }
+                }
+            }
+            Err(error) => display_warning(&format!(
+                "Could not check which instances are still running: {}",
+                error
+            )),
+        }
+    }
+
+    let summaries: Vec<_> = summarize(&events, Utc::now())
+        .into_iter()
+        .filter(|summary| name.is_none_or(|name| summary.instance == name))
+        .filter(|summary| month.is_none_or(|month| summary.month == month))
+        .collect();
+
+    io::display_usage(summaries, format)?;
+    Ok(String::new())
+}
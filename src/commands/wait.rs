@@ -0,0 +1,30 @@
+use crate::cli::progress::Progress;
+use crate::utils::{
+    client::FlyClient,
+    error::SpsdError,
+    machines::{get_instance, wait_for_state},
+    types::WaitState,
+};
+use std::time::Duration;
+
+pub async fn wait_for_instance(
+    client: &FlyClient,
+    name: &str,
+    state: WaitState,
+    timeout_secs: u64,
+) -> Result<String, SpsdError> {
+    let instance = get_instance(client, name).await?;
+    Progress::new()
+        .step(
+            &format!("Waiting for {} to be {}", name, state),
+            wait_for_state(
+                client,
+                &instance.machine_id,
+                None,
+                state,
+                Duration::from_secs(timeout_secs),
+            ),
+        )
+        .await?;
+    Ok(format!("Instance {} is {}", name, state))
+}
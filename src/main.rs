@@ -1,16 +1,42 @@
 use clap::ArgMatches;
 use cli::io::prompt_instance_creation;
-use std::error::Error;
-use utils::types::InstanceInput;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::process;
+use utils::client::FlyClient;
+use utils::config::{self, active_profile_name, read_config_file};
+use utils::connector::{get_connector, Connector};
+use utils::credentials;
+use utils::env::read_env_file;
+use utils::error::SpsdError;
+use utils::types::{
+    Config, ConnectorKind, InitCommand, InstanceInput, IpType, MachineOptions, OutputFormat,
+    PortForward, PortSpec, WaitState,
+};
 
 mod cli;
 mod commands;
 mod utils;
 
-fn main() {
+#[tokio::main]
+async fn main() {
     let command = cli::arg_parsing::read_input();
 
-    let result: Result<String, Box<dyn Error>> = match command.subcommand() {
+    match run(command).await {
+        Ok(message) => cli::io::display_success(&message),
+        Err(error) => {
+            cli::io::display_error(&error);
+            process::exit(error.exit_code());
+        }
+    };
+}
+
+async fn run(command: ArgMatches) -> Result<String, SpsdError> {
+    if let Some(profile) = command.get_one::<String>("profile") {
+        config::select_profile(profile);
+    }
+
+    match command.subcommand() {
         Some(("new", args)) => {
             let name = args.try_get_one::<String>("name").unwrap().cloned();
             let image = args.try_get_one::<String>("image").unwrap().cloned();
@@ -18,18 +44,31 @@ fn main() {
             let memory = args.try_get_one::<u32>("memory").unwrap().cloned();
             let volume = args.try_get_one::<u32>("volume").unwrap().cloned();
             let region = args.try_get_one::<String>("region").unwrap().cloned();
-            let port = args.try_get_one::<u16>("port").unwrap().cloned();
+            let ports = get_ports(args);
+            let options = MachineOptions {
+                env: get_env(args)?,
+                init: InitCommand::new(
+                    args.get_one::<Vec<String>>("entrypoint").cloned(),
+                    args.get_one::<Vec<String>>("cmd").cloned(),
+                    args.get_flag("keep-image-cmd"),
+                ),
+                ..MachineOptions::default()
+            };
+            let format = get_output_format(args);
 
+            // anything not given falls back to the profile's defaults before prompting
+            let defaults = config::read_config()?;
             let instance: InstanceInput = InstanceInput {
                 name,
-                image,
-                cpus,
-                memory,
-                volume,
-                region,
-                port,
+                image: image.or(defaults.image),
+                cpus: cpus.or(defaults.cpus),
+                memory: memory.or(defaults.memory),
+                volume: volume.or(defaults.volume),
+                region: region.or(defaults.region),
+                ports,
             };
 
+            let client = FlyClient::from_config()?;
             let instance = prompt_instance_creation(instance);
 
             match (
@@ -39,7 +78,7 @@ fn main() {
                 instance.memory,
                 instance.volume,
                 instance.region,
-                instance.port,
+                instance.ports,
             ) {
                 (
                     Some(name),
@@ -48,66 +87,334 @@ fn main() {
                     Some(memory),
                     Some(volume),
                     Some(region),
-                    port,
-                ) => commands::new::create_new_instance(
-                    &name, &image, cpus, memory, volume, &region, port,
-                ),
-                _ => Err("Error in argument parsing, use -h to see valid values".into()),
+                    ports,
+                ) => {
+                    commands::new::create_new_instance(
+                        &client,
+                        &name,
+                        &image,
+                        cpus,
+                        memory,
+                        volume,
+                        &region,
+                        &ports,
+                        &options,
+                        !args.get_flag("no-wait"),
+                        format,
+                    )
+                    .await
+                }
+                _ => Err(SpsdError::Validation(String::from(
+                    "Error in argument parsing, use -h to see valid values",
+                ))),
             }
         }
 
         Some(("start", args)) => {
-            handle_command_with_name(args, |name| commands::start::start_instance(name))
+            let client = FlyClient::from_config()?;
+            let connector = get_connector_from_args(args, &client)?;
+            commands::start::start_instance(&client, connector.as_ref(), get_name(args)?).await
         }
 
         Some(("stop", args)) => {
-            handle_command_with_name(args, |name| commands::stop::stop_instance(name))
+            commands::stop::stop_instance(&FlyClient::from_config()?, get_name(args)?).await
         }
 
-        Some(("sl", args)) => handle_command_with_name(args, |name| commands::sl::serverless(name)),
+        Some(("sl", args)) => {
+            let client = FlyClient::from_config()?;
+            let connector = get_connector_from_args(args, &client)?;
+            let idle_timeout = args.get_one::<u64>("idle-timeout").copied().unwrap_or(0);
+            commands::sl::serverless(&client, connector.as_ref(), get_name(args)?, idle_timeout)
+                .await
+        }
 
-        Some(("destroy", args)) => {
-            handle_command_with_name(args, |name| commands::destroy::destroy_instance(name))
+        Some(("resize", args)) => {
+            commands::resize::resize_instance(
+                &FlyClient::from_config()?,
+                get_name(args)?,
+                args.get_one::<String>("image").map(String::as_str),
+                args.get_one::<u32>("cpus").copied(),
+                args.get_one::<u32>("memory").copied(),
+                args.get_one::<u32>("volume").copied(),
+                &get_ports(args),
+                &get_env(args)?,
+                &args
+                    .get_many::<String>("unset-env")
+                    .map(|keys| keys.cloned().collect::<Vec<_>>())
+                    .unwrap_or_default(),
+                get_output_format(args),
+            )
+            .await
+        }
+
+        Some(("clone", args)) => {
+            let source = args.get_one::<String>("source").unwrap();
+            commands::clone::clone_instance(
+                &FlyClient::from_config()?,
+                source,
+                get_name(args)?,
+                args.get_one::<String>("region").map(String::as_str),
+                &get_ports(args),
+                get_output_format(args),
+            )
+            .await
         }
 
-        Some(("profile", args)) => {
-            let api_key = args.try_get_one::<String>("api_key");
-            let allocate_ip = args.try_get_one::<String>("allocate_ip");
+        Some(("cp", args)) => {
+            let client = FlyClient::from_config()?;
+            let connector = get_connector_from_args(args, &client)?;
+            commands::cp::copy(
+                &client,
+                connector.as_ref(),
+                args.get_one::<String>("source").unwrap(),
+                args.get_one::<String>("destination").unwrap(),
+                args.get_flag("recursive"),
+            )
+            .await
+        }
+
+        Some(("exec", args)) => {
+            let command: Vec<String> = args
+                .get_many::<String>("command")
+                .unwrap()
+                .cloned()
+                .collect();
+            let client = FlyClient::from_config()?;
+            let connector = get_connector_from_args(args, &client)?;
+            commands::exec::exec_command(
+                &client,
+                connector.as_ref(),
+                get_name(args)?,
+                &command,
+                args.get_one::<u64>("timeout").copied().unwrap_or(60),
+                args.get_flag("stop"),
+            )
+            .await
+        }
+
+        Some(("forward", args)) => {
+            let client = FlyClient::from_config()?;
+            let connector = get_connector_from_args(args, &client)?;
+            let ports = *args.get_one::<PortForward>("ports").unwrap();
+            commands::forward::forward_port(&client, connector.as_ref(), get_name(args)?, ports)
+                .await
+        }
+
+        Some(("secrets", args)) => {
+            let client = FlyClient::from_config()?;
+            match args.subcommand() {
+                Some(("list", args)) => {
+                    commands::secrets::list_secrets(&client, get_output_format(args)).await
+                }
+                Some(("set", args)) => {
+                    let secrets = args
+                        .get_many::<(String, String)>("secrets")
+                        .unwrap()
+                        .cloned()
+                        .collect();
+                    commands::secrets::set_secrets(&client, &secrets, args.get_flag("stage")).await
+                }
+                Some(("unset", args)) => {
+                    let keys: Vec<String> =
+                        args.get_many::<String>("keys").unwrap().cloned().collect();
+                    commands::secrets::unset_secrets(&client, &keys, args.get_flag("stage")).await
+                }
+                _ => Err(SpsdError::Validation(String::from("Subcommand invalid"))),
+            }
+        }
+
+        Some(("wait", args)) => {
+            let timeout = *args.get_one::<u64>("timeout").unwrap();
+            commands::wait::wait_for_instance(
+                &FlyClient::from_config()?,
+                get_name(args)?,
+                *args.get_one::<WaitState>("state").unwrap(),
+                timeout,
+            )
+            .await
+        }
+
+        Some(("ip", args)) => {
+            let client = FlyClient::from_config()?;
+            match args.subcommand() {
+                Some(("list", args)) => {
+                    commands::ip::list_ips(&client, get_output_format(args)).await
+                }
+                Some(("allocate", args)) => {
+                    let ip_type = if args.get_flag("v6") {
+                        IpType::V6
+                    } else if args.get_flag("shared") {
+                        IpType::SharedV4
+                    } else {
+                        IpType::V4
+                    };
+                    let region = args.get_one::<String>("region").map(String::as_str);
+                    commands::ip::allocate_ip(&client, ip_type, region).await
+                }
+                Some(("release", args)) => {
+                    let address = args.get_one::<String>("address").unwrap();
+                    commands::ip::release_ip(&client, address).await
+                }
+                _ => Err(SpsdError::Validation(String::from("Subcommand invalid"))),
+            }
+        }
 
-            match (api_key, allocate_ip) {
-                (Ok(Some(app_name)), Ok(Some(allocate_ip))) => {
-                    let should_allocate = allocate_ip == "y";
-                    commands::profile::modify_profile(app_name, should_allocate)
+        Some(("snapshot", args)) => {
+            let client = FlyClient::from_config()?;
+            match args.subcommand() {
+                Some(("create", args)) => {
+                    commands::snapshot::create_snapshot(&client, get_name(args)?).await
                 }
-                _ => Err("Error in argument parsing, use -h to see valid values".into()),
+                Some(("list", args)) => {
+                    commands::snapshot::list_snapshots(
+                        &client,
+                        get_name(args)?,
+                        get_output_format(args),
+                    )
+                    .await
+                }
+                Some(("restore", args)) => {
+                    commands::snapshot::restore_snapshot(
+                        &client,
+                        get_name(args)?,
+                        args.get_one::<String>("snapshot").map(String::as_str),
+                        args.get_flag("delete-old"),
+                    )
+                    .await
+                }
+                _ => Err(SpsdError::Validation(String::from("Subcommand invalid"))),
             }
         }
 
+        Some(("destroy", args)) => {
+            commands::destroy::destroy_instance(&FlyClient::from_config()?, get_name(args)?).await
+        }
+
+        Some(("profile", args)) => match args.subcommand() {
+            Some(("add", args)) => {
+                let name = get_name(args)?;
+                let api_key = args.get_one::<String>("api_key").unwrap();
+                let allocate_ip = args.get_one::<String>("allocate_ip").unwrap() == "y";
+                let settings = Config {
+                    fly_org: args.get_one::<String>("org").cloned(),
+                    fly_app_name: args.get_one::<String>("app").cloned(),
+                    region: args.get_one::<String>("region").cloned(),
+                    image: args.get_one::<String>("image").cloned(),
+                    cpus: args.get_one::<u32>("cpus").copied(),
+                    memory: args.get_one::<u32>("memory").copied(),
+                    volume: args.get_one::<u32>("volume").copied(),
+                    ..Config::default()
+                };
+                commands::profile::add_profile(
+                    name,
+                    api_key,
+                    allocate_ip,
+                    settings,
+                    &config::get_config_path()?,
+                    credentials::store_api_key,
+                )
+                .await
+            }
+            Some(("use", args)) => commands::profile::use_profile(get_name(args)?),
+            Some(("list", args)) => commands::profile::list_profiles(get_output_format(args)),
+            Some(_) => Err(SpsdError::Validation(String::from("Subcommand invalid"))),
+            // the original `spsd profile <API_KEY> <ALLOCATE_IP>` updates the active profile
+            None => match (
+                args.get_one::<String>("api_key"),
+                args.get_one::<String>("allocate_ip"),
+            ) {
+                (Some(api_key), Some(allocate_ip)) => {
+                    commands::profile::add_profile(
+                        &active_profile_name(&read_config_file()?),
+                        api_key,
+                        allocate_ip == "y",
+                        Config::default(),
+                        &config::get_config_path()?,
+                        credentials::store_api_key,
+                    )
+                    .await
+                }
+                _ => Err(SpsdError::Validation(String::from(
+                    "Error in argument parsing, use -h to see valid values",
+                ))),
+            },
+        },
+
         Some(("list", args)) => {
+            let client = FlyClient::from_config()?;
+            let format = get_output_format(args);
             let list_ips = args.try_get_one::<String>("ip");
             match list_ips {
-                Ok(Some(_)) => commands::list::list_instances(true),
-                Ok(None) => commands::list::list_instances(false),
-                _ => Err("Error in argument parsing, use -h to see valid values".into()),
+                Ok(Some(_)) => commands::list::list_instances(&client, true, format).await,
+                Ok(None) => commands::list::list_instances(&client, false, format).await,
+                _ => Err(SpsdError::Validation(String::from(
+                    "Error in argument parsing, use -h to see valid values",
+                ))),
             }
         }
 
-        _ => Err("Subcommand invalid".into()),
-    };
+        Some(("apply", args)) => {
+            let path = args.get_one::<String>("file").unwrap();
+            let dry_run = args.get_flag("dry-run");
+            commands::apply::apply_manifest(
+                &FlyClient::from_config()?,
+                Path::new(path),
+                dry_run,
+                get_output_format(args),
+            )
+            .await
+        }
 
-    match result {
-        Ok(message) => cli::io::display_success(&message),
-        Err(message) => cli::io::display_error(message),
-    };
+        Some(("usage", args)) => {
+            let name = args.get_one::<String>("name").map(String::as_str);
+            let month = args.get_one::<String>("month").map(String::as_str);
+            // usage is recorded locally, so it can still be shown without a working profile
+            let client = FlyClient::from_config().ok();
+            commands::usage::show_usage(client.as_ref(), name, month, get_output_format(args)).await
+        }
+
+        _ => Err(SpsdError::Validation(String::from("Subcommand invalid"))),
+    }
 }
 
-fn handle_command_with_name<F>(args: &ArgMatches, function: F) -> Result<String, Box<dyn Error>>
-where
-    F: Fn(&str) -> Result<String, Box<dyn Error>>,
-{
+fn get_name(args: &ArgMatches) -> Result<&str, SpsdError> {
     if let Ok(Some(name)) = args.try_get_one::<String>("name") {
-        function(&name)
+        Ok(name)
     } else {
-        Err("Please provide the name of the instance".into())
+        Err(SpsdError::Validation(String::from(
+            "Please provide the name of the instance",
+        )))
     }
 }
+
+fn get_ports(args: &ArgMatches) -> Vec<PortSpec> {
+    args.get_many::<PortSpec>("port")
+        .map(|ports| ports.cloned().collect())
+        .unwrap_or_default()
+}
+
+// variables from --env-file, overridden by any given with --env
+fn get_env(args: &ArgMatches) -> Result<BTreeMap<String, String>, SpsdError> {
+    let mut env = match args.get_one::<String>("env-file") {
+        Some(path) => read_env_file(Path::new(path))?,
+        None => BTreeMap::new(),
+    };
+    if let Some(pairs) = args.get_many::<(String, String)>("env") {
+        env.extend(pairs.cloned());
+    }
+    Ok(env)
+}
+
+fn get_output_format(args: &ArgMatches) -> OutputFormat {
+    args.get_one::<OutputFormat>("output")
+        .copied()
+        .unwrap_or(OutputFormat::Table)
+}
+
+fn get_connector_from_args(
+    args: &ArgMatches,
+    client: &FlyClient,
+) -> Result<Box<dyn Connector>, SpsdError> {
+    let kind = args.get_one::<ConnectorKind>("connector").copied();
+    get_connector(kind, client.app_name())
+}
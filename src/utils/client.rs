@@ -0,0 +1,454 @@
+use super::config::{get_api_key, get_api_url, get_app_name, get_graphql_url};
+use super::error::SpsdError;
+use super::registry;
+use super::types::{
+    App, IpAddress, IpType, Machine, Machines, Secret, Snapshot, Volume, VolumeExtension, WaitState,
+};
+use reqwest::header::{HeaderMap, HeaderValue, CONTENT_TYPE};
+use reqwest::{Client, Method, RequestBuilder};
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+// how much longer than a wait's own timeout to give fly to answer it before giving up on the call
+const WAIT_RESPONSE_GRACE_SECS: u64 = 5;
+
+// a single client shared by every call to the machines api so connections get pooled. the api
+// key is added to each call to fly rather than to the client, which also talks to image registries
+pub struct FlyClient {
+    client: Client,
+    app_name: String,
+    api_url: String,
+    hostname: String,
+    graphql_url: String,
+    api_key: String,
+    // looks images up here instead of in the registry they name, for tests
+    registry_url: Option<String>,
+}
+
+impl FlyClient {
+    pub fn new(
+        api_url: &str,
+        graphql_url: &str,
+        app_name: &str,
+        api_key: &str,
+    ) -> Result<Self, SpsdError> {
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+
+        let client = Client::builder().default_headers(headers).build()?;
+        Ok(FlyClient {
+            client,
+            app_name: app_name.to_string(),
+            api_url: api_url.trim_end_matches('/').to_string(),
+            hostname: format!("{}/apps/{}", api_url.trim_end_matches('/'), app_name),
+            graphql_url: graphql_url.to_string(),
+            api_key: api_key.to_string(),
+            registry_url: None,
+        })
+    }
+
+    #[cfg(test)]
+    pub fn with_registry_url(mut self, registry_url: &str) -> Self {
+        self.registry_url = Some(registry_url.to_string());
+        self
+    }
+
+    pub fn from_config() -> Result<Self, SpsdError> {
+        Self::new(
+            &get_api_url()?,
+            &get_graphql_url()?,
+            &get_app_name()?,
+            &get_api_key()?,
+        )
+    }
+
+    pub fn app_name(&self) -> &str {
+        &self.app_name
+    }
+
+    // the ENTRYPOINT the image was built with. the api key is only sent along to fly's registry
+    pub async fn image_entrypoint(&self, image: &str) -> Result<Vec<String>, SpsdError> {
+        registry::image_entrypoint(self, image).await
+    }
+
+    // for the registry lookups, which share the connection pool but not the authorization
+    pub(super) fn http(&self) -> &Client {
+        &self.client
+    }
+
+    pub(super) fn api_key(&self) -> &str {
+        &self.api_key
+    }
+
+    pub(super) fn registry_url(&self) -> Option<&str> {
+        self.registry_url.as_deref()
+    }
+
+    pub async fn get_app(&self) -> Result<App, SpsdError> {
+        self.request(Method::GET, "", None).await
+    }
+
+    pub async fn create_app(&self, org_slug: &str) -> Result<(), SpsdError> {
+        let body = json!({
+            "app_name": self.app_name,
+            "org_slug": org_slug
+        });
+        let request = self
+            .client
+            .post(format!("{}/apps", self.api_url))
+            .bearer_auth(&self.api_key);
+        self.send::<Value>(request, Some(body)).await?;
+        Ok(())
+    }
+
+    // the email of the user the api key belongs to, which fails if the key isn't valid
+    pub async fn get_viewer(&self) -> Result<String, SpsdError> {
+        let data: Value = self
+            .graphql("query { viewer { email } }", json!({}))
+            .await?;
+        Ok(data["viewer"]["email"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string())
+    }
+
+    pub async fn list_machines(&self) -> Result<Machines, SpsdError> {
+        self.request(Method::GET, "/machines", None).await
+    }
+
+    pub async fn get_machine(&self, machine_id: &str) -> Result<Machine, SpsdError> {
+        self.request(Method::GET, &format!("/machines/{}", machine_id), None)
+            .await
+    }
+
+    pub async fn get_machine_config(&self, machine_id: &str) -> Result<Value, SpsdError> {
+        let machine: Value = self
+            .request(Method::GET, &format!("/machines/{}", machine_id), None)
+            .await?;
+        Ok(machine["config"].clone())
+    }
+
+    pub async fn update_machine(
+        &self,
+        machine_id: &str,
+        config: Value,
+        skip_launch: bool,
+    ) -> Result<Machine, SpsdError> {
+        let body = json!({
+            "config": config,
+            "skip_launch": skip_launch
+        });
+        self.request(
+            Method::POST,
+            &format!("/machines/{}", machine_id),
+            Some(body),
+        )
+        .await
+    }
+
+    pub async fn create_machine(&self, body: Value) -> Result<Machine, SpsdError> {
+        self.request(Method::POST, "/machines", Some(body)).await
+    }
+
+    pub async fn delete_machine(&self, machine_id: &str, force: bool) -> Result<Value, SpsdError> {
+        self.request(
+            Method::DELETE,
+            &format!("/machines/{}?force={}", machine_id, force),
+            None,
+        )
+        .await
+    }
+
+    pub async fn start_machine(&self, machine_id: &str) -> Result<Value, SpsdError> {
+        self.request(
+            Method::POST,
+            &format!("/machines/{}/start", machine_id),
+            None,
+        )
+        .await
+    }
+
+    pub async fn stop_machine(&self, machine_id: &str) -> Result<Value, SpsdError> {
+        self.request(
+            Method::POST,
+            &format!("/machines/{}/stop", machine_id),
+            None,
+        )
+        .await
+    }
+
+    // fly answers 408 if the machine isn't in `state` after `timeout_secs` (at most 60)
+    pub async fn wait_machine(
+        &self,
+        machine_id: &str,
+        instance_id: Option<&str>,
+        state: WaitState,
+        timeout_secs: u64,
+    ) -> Result<Value, SpsdError> {
+        let mut path = format!(
+            "/machines/{}/wait?state={}&timeout={}",
+            machine_id, state, timeout_secs
+        );
+        if let Some(instance_id) = instance_id {
+            path.push_str(&format!("&instance_id={}", instance_id));
+        }
+        let request = self
+            .client
+            .get(self.hostname.clone() + &path)
+            .bearer_auth(&self.api_key)
+            .timeout(Duration::from_secs(timeout_secs + WAIT_RESPONSE_GRACE_SECS));
+        self.send(request, None).await
+    }
+
+    pub async fn list_volumes(&self) -> Result<Vec<Volume>, SpsdError> {
+        self.request(Method::GET, "/volumes", None).await
+    }
+
+    pub async fn create_volume(
+        &self,
+        name: &str,
+        volume_gb: u32,
+        region: &str,
+    ) -> Result<Volume, SpsdError> {
+        let body = json!({
+            "name": name,
+            "region": region,
+            "size_gb": volume_gb
+        });
+        self.request(Method::POST, "/volumes", Some(body)).await
+    }
+
+    pub async fn fork_volume(
+        &self,
+        name: &str,
+        volume_gb: u32,
+        region: &str,
+        source_volume_id: &str,
+    ) -> Result<Volume, SpsdError> {
+        let body = json!({
+            "name": name,
+            "region": region,
+            "size_gb": volume_gb,
+            "source_volume_id": source_volume_id
+        });
+        self.request(Method::POST, "/volumes", Some(body)).await
+    }
+
+    pub async fn create_volume_from_snapshot(
+        &self,
+        name: &str,
+        volume_gb: u32,
+        region: &str,
+        snapshot_id: &str,
+    ) -> Result<Volume, SpsdError> {
+        let body = json!({
+            "name": name,
+            "region": region,
+            "size_gb": volume_gb,
+            "snapshot_id": snapshot_id
+        });
+        self.request(Method::POST, "/volumes", Some(body)).await
+    }
+
+    pub async fn list_snapshots(&self, volume_id: &str) -> Result<Vec<Snapshot>, SpsdError> {
+        self.request(
+            Method::GET,
+            &format!("/volumes/{}/snapshots", volume_id),
+            None,
+        )
+        .await
+    }
+
+    pub async fn create_snapshot(&self, volume_id: &str) -> Result<Value, SpsdError> {
+        self.request(
+            Method::POST,
+            &format!("/volumes/{}/snapshots", volume_id),
+            None,
+        )
+        .await
+    }
+
+    pub async fn extend_volume(
+        &self,
+        volume_id: &str,
+        volume_gb: u32,
+    ) -> Result<VolumeExtension, SpsdError> {
+        let body = json!({ "size_gb": volume_gb });
+        self.request(
+            Method::PUT,
+            &format!("/volumes/{}/extend", volume_id),
+            Some(body),
+        )
+        .await
+    }
+
+    pub async fn delete_volume(&self, volume_id: &str) -> Result<Value, SpsdError> {
+        self.request(Method::DELETE, &format!("/volumes/{}", volume_id), None)
+            .await
+    }
+
+    pub async fn list_secrets(&self) -> Result<Vec<Secret>, SpsdError> {
+        let data: Value = self
+            .graphql(
+                "query($appName: String!) { app(name: $appName) { secrets { name digest createdAt } } }",
+                json!({ "appName": self.app_name }),
+            )
+            .await?;
+        Ok(serde_json::from_value(data["app"]["secrets"].clone())?)
+    }
+
+    pub async fn set_secrets(&self, secrets: &BTreeMap<String, String>) -> Result<(), SpsdError> {
+        let secrets: Vec<Value> = secrets
+            .iter()
+            .map(|(key, value)| json!({ "key": key, "value": value }))
+            .collect();
+        self.graphql::<Value>(
+            "mutation($input: SetSecretsInput!) { setSecrets(input: $input) { app { name } } }",
+            json!({ "input": { "appId": self.app_name, "secrets": secrets } }),
+        )
+        .await?;
+        Ok(())
+    }
+
+    pub async fn unset_secrets(&self, keys: &[String]) -> Result<(), SpsdError> {
+        self.graphql::<Value>(
+            "mutation($input: UnsetSecretsInput!) { unsetSecrets(input: $input) { app { name } } }",
+            json!({ "input": { "appId": self.app_name, "keys": keys } }),
+        )
+        .await?;
+        Ok(())
+    }
+
+    pub async fn list_ip_addresses(&self) -> Result<Vec<IpAddress>, SpsdError> {
+        let data: Value = self
+            .graphql(
+                "query($appName: String!) { app(name: $appName) { sharedIpAddress ipAddresses { nodes { id address type region createdAt } } } }",
+                json!({ "appName": self.app_name }),
+            )
+            .await?;
+        let mut addresses: Vec<IpAddress> =
+            serde_json::from_value(data["app"]["ipAddresses"]["nodes"].clone())?;
+        if let Some(address) = data["app"]["sharedIpAddress"].as_str() {
+            addresses.push(IpAddress {
+                id: String::new(),
+                address: address.to_string(),
+                ip_type: IpType::SharedV4,
+                region: None,
+                created_at: None,
+            });
+        }
+        Ok(addresses)
+    }
+
+    // fly doesn't return the address when allocating the shared ipv4 address
+    pub async fn allocate_ip_address(
+        &self,
+        ip_type: IpType,
+        region: Option<&str>,
+    ) -> Result<Option<IpAddress>, SpsdError> {
+        let data: Value = self
+            .graphql(
+                "mutation($input: AllocateIPAddressInput!) { allocateIpAddress(input: $input) { ipAddress { id address type region createdAt } } }",
+                json!({ "input": { "appId": self.app_name, "type": ip_type, "region": region } }),
+            )
+            .await?;
+        Ok(serde_json::from_value(
+            data["allocateIpAddress"]["ipAddress"].clone(),
+        )?)
+    }
+
+    pub async fn release_ip_address(&self, address: &str) -> Result<(), SpsdError> {
+        self.graphql::<Value>(
+            "mutation($input: ReleaseIPAddressInput!) { releaseIpAddress(input: $input) { app { name } } }",
+            json!({ "input": { "appId": self.app_name, "ip": address } }),
+        )
+        .await?;
+        Ok(())
+    }
+
+    // graphql reports most failures as a successful response with an errors array
+    async fn graphql<T: DeserializeOwned>(
+        &self,
+        query: &str,
+        variables: Value,
+    ) -> Result<T, SpsdError> {
+        let body = json!({ "query": query, "variables": variables });
+        let response = self
+            .client
+            .post(&self.graphql_url)
+            .bearer_auth(&self.api_key)
+            .body(body.to_string())
+            .send()
+            .await?;
+        let status = response.status().as_u16();
+        let response_body: Value = serde_json::from_str(&response.text().await?)?;
+
+        let message = response_body["errors"][0]["message"]
+            .as_str()
+            .map(String::from);
+        match (status, message) {
+            (401 | 403, message) => Err(SpsdError::Auth(message.unwrap_or_default())),
+            (_, Some(message)) if message.contains("Could not find") => {
+                Err(SpsdError::NotFound(message))
+            }
+            (_, Some(message)) if message.contains("not authorized") => {
+                Err(SpsdError::Auth(message))
+            }
+            (_, Some(message)) => Err(SpsdError::Api { status, message }),
+            _ => Ok(serde_json::from_value(response_body["data"].clone())?),
+        }
+    }
+
+    async fn request<T: DeserializeOwned>(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<Value>,
+    ) -> Result<T, SpsdError> {
+        let request = self
+            .client
+            .request(method, self.hostname.clone() + path)
+            .bearer_auth(&self.api_key);
+        self.send(request, body).await
+    }
+
+    // sends the request and maps fly's (and registries') error statuses to spsd's errors
+    pub(super) async fn send<T: DeserializeOwned>(
+        &self,
+        mut request: RequestBuilder,
+        body: Option<Value>,
+    ) -> Result<T, SpsdError> {
+        if let Some(b) = body {
+            request = request.body(b.to_string());
+        }
+
+        let response = request.send().await?;
+        let status = response.status();
+        let response_body = response.text().await?;
+        if status.is_success() {
+            // some endpoints (like creating a snapshot) answer with an empty body
+            match response_body.trim() {
+                "" => Ok(serde_json::from_value(Value::Null)?),
+                body => Ok(serde_json::from_str(body)?),
+            }
+        } else {
+            let message = serde_json::from_str::<Value>(&response_body)
+                .ok()
+                .and_then(|value| {
+                    value
+                        .get("error")
+                        .and_then(|v| v.as_str())
+                        .map(String::from)
+                })
+                .unwrap_or(response_body);
+
+            Err(match status.as_u16() {
+                401 | 403 => SpsdError::Auth(message),
+                404 => SpsdError::NotFound(message),
+                status => SpsdError::Api { status, message },
+            })
+        }
+    }
+}
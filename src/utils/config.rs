@@ -1,13 +1,26 @@
 use dirs;
-use serde_json;
-use std::error::Error;
-use std::fs::File;
+use serde_json::{self, Value};
+use std::env;
+use std::fs::{self, File, OpenOptions};
 use std::io::{self, Read, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
 
-use super::types::Config;
+use super::credentials;
+use super::error::SpsdError;
+use super::types::{Config, ConfigFile};
 
-fn get_config_path() -> io::Result<PathBuf> {
+pub const DEFAULT_PROFILE: &str = "default";
+
+const DEFAULT_API_URL: &str = "https://api.machines.dev/v1";
+const DEFAULT_GRAPHQL_URL: &str = "https://api.fly.io/graphql";
+
+static SELECTED_PROFILE: OnceLock<String> = OnceLock::new();
+
+pub fn get_config_path() -> io::Result<PathBuf> {
+    if let Ok(path) = env::var("SPSD_CONFIG") {
+        return Ok(PathBuf::from(path));
+    }
     match dirs::config_dir() {
         Some(path) => Ok(path.join("spsd_config.json")),
         None => Err(io::Error::new(
@@ -17,35 +30,174 @@ fn get_config_path() -> io::Result<PathBuf> {
     }
 }
 
-fn read_config() -> io::Result<Config> {
-    let path = get_config_path()?;
+// other files spsd keeps (e.g. the usage ledger) live next to the config file
+pub fn get_data_path(file_name: &str) -> io::Result<PathBuf> {
+    Ok(get_config_path()?.with_file_name(file_name))
+}
+
+pub fn read_config_file() -> Result<ConfigFile, SpsdError> {
+    read_config_file_at(&get_config_path()?)
+}
+
+pub fn read_config_file_at(path: &Path) -> Result<ConfigFile, SpsdError> {
     if path.exists() {
         let mut file = File::open(path)?;
         let mut contents = String::new();
         file.read_to_string(&mut contents)?;
-        serde_json::from_str(&contents).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+        Ok(parse_config_file(&contents)?)
     } else {
-        Ok(Config::new())
+        Ok(ConfigFile::default())
     }
 }
 
-pub fn write_config(config: &Config) -> io::Result<()> {
-    let path = get_config_path()?;
-    let mut file = File::create(path)?;
-    let contents = serde_json::to_string(config)?;
-    file.write_all(contents.as_bytes())
+// config files from before profiles hold a single flat profile, which becomes "default"
+fn parse_config_file(contents: &str) -> Result<ConfigFile, serde_json::Error> {
+    let value: Value = serde_json::from_str(contents)?;
+    if value.get("profiles").is_some() {
+        return serde_json::from_value(value);
+    }
+    let mut config_file = ConfigFile::default();
+    config_file
+        .profiles
+        .insert(DEFAULT_PROFILE.to_string(), serde_json::from_value(value)?);
+    Ok(config_file)
 }
 
-pub fn get_api_key() -> Result<String, Box<dyn Error>> {
-    let config = read_config()?;
-    config
-        .fly_api_key
-        .ok_or_else(|| "FLY_API_KEY not set in config file".into())
+pub fn write_config_file(config_file: &ConfigFile) -> Result<(), SpsdError> {
+    write_config_file_at(&get_config_path()?, config_file)
 }
 
-pub fn get_app_name() -> Result<String, Box<dyn Error>> {
+pub fn write_config_file_at(path: &Path, config_file: &ConfigFile) -> Result<(), SpsdError> {
+    let contents = serde_json::to_string_pretty(config_file)?;
+    write_private_file(path, &contents)?;
+    Ok(())
+}
+
+// writes a file only the current user can read or write
+pub fn write_private_file(path: &Path, contents: &str) -> io::Result<()> {
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+        options.mode(0o600);
+        // the mode only applies to new files, so older ones are tightened too
+        if path.exists() {
+            fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+        }
+    }
+    options.open(path)?.write_all(contents.as_bytes())
+}
+
+// --profile for this run, set once while parsing arguments
+pub fn select_profile(name: &str) {
+    let _ = SELECTED_PROFILE.set(name.to_string());
+}
+
+// --profile, then SPSD_PROFILE, then the profile chosen with `spsd profile use`
+pub fn active_profile_name(config_file: &ConfigFile) -> String {
+    SELECTED_PROFILE
+        .get()
+        .cloned()
+        .or_else(|| env::var("SPSD_PROFILE").ok())
+        .or_else(|| config_file.current_profile.clone())
+        .unwrap_or_else(|| DEFAULT_PROFILE.to_string())
+}
+
+// the settings of the active profile
+pub fn read_config() -> Result<Config, SpsdError> {
+    let mut config_file = read_config_file()?;
+    let name = active_profile_name(&config_file);
+    match config_file.profiles.remove(&name) {
+        Some(config) => Ok(config),
+        None if name == DEFAULT_PROFILE => Ok(Config::default()),
+        None => Err(SpsdError::ConfigMissing(format!("Profile {}", name))),
+    }
+}
+
+// FLY_API_TOKEN takes precedence and is never written anywhere
+pub fn get_api_key() -> Result<String, SpsdError> {
+    if let Ok(token) = env::var("FLY_API_TOKEN") {
+        return Ok(token);
+    }
+    let mut config_file = read_config_file()?;
+    let name = active_profile_name(&config_file);
+    let config = config_file
+        .profiles
+        .get_mut(&name)
+        .ok_or_else(|| SpsdError::ConfigMissing(String::from("FLY_API_KEY")))?;
+    match (config.fly_api_key.take(), config.credential) {
+        (Some(api_key), _) => {
+            // keys from older config files are moved out of the plain text config
+            if let Ok(store) = credentials::store_api_key(&name, &api_key) {
+                config.credential = Some(store);
+                let _ = write_config_file(&config_file);
+            }
+            Ok(api_key)
+        }
+        (None, Some(store)) => credentials::load_api_key(&name, store),
+        (None, None) => Err(SpsdError::ConfigMissing(String::from("FLY_API_KEY"))),
+    }
+}
+
+pub fn get_app_name() -> Result<String, SpsdError> {
     let config = read_config()?;
     config
         .fly_app_name
-        .ok_or_else(|| "FLY_APP_NAME not set in config file".into())
+        .ok_or_else(|| SpsdError::ConfigMissing(String::from("FLY_APP_NAME")))
+}
+
+pub fn get_api_url() -> Result<String, SpsdError> {
+    if let Ok(url) = env::var("SPSD_API_URL") {
+        return Ok(url);
+    }
+    Ok(api_url_for(&read_config()?))
+}
+
+// secrets (and other app level settings) are only available through fly's graphql api
+pub fn get_graphql_url() -> Result<String, SpsdError> {
+    if let Ok(url) = env::var("SPSD_GRAPHQL_URL") {
+        return Ok(url);
+    }
+    Ok(graphql_url_for(&read_config()?))
+}
+
+// the urls for a profile that needn't be the active one (or exist yet), e.g. while adding it
+pub fn api_url_for(config: &Config) -> String {
+    env::var("SPSD_API_URL")
+        .ok()
+        .or_else(|| config.fly_api_url.clone())
+        .unwrap_or_else(|| DEFAULT_API_URL.to_string())
+}
+
+pub fn graphql_url_for(config: &Config) -> String {
+    env::var("SPSD_GRAPHQL_URL")
+        .ok()
+        .or_else(|| config.fly_graphql_url.clone())
+        .unwrap_or_else(|| DEFAULT_GRAPHQL_URL.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flat_config_becomes_default_profile() {
+        let config_file =
+            parse_config_file(r#"{"fly_api_key":"key","fly_app_name":"app","connector":"ssh"}"#)
+                .unwrap();
+
+        assert!(config_file.current_profile.is_none());
+        let profile = &config_file.profiles[DEFAULT_PROFILE];
+        assert_eq!(profile.fly_app_name.as_deref(), Some("app"));
+        assert_eq!(profile.connector.as_deref(), Some("ssh"));
+
+        let contents = serde_json::to_string(&config_file).unwrap();
+        let reparsed = parse_config_file(&contents).unwrap();
+        assert_eq!(reparsed.profiles.len(), 1);
+        assert_eq!(
+            reparsed.profiles[DEFAULT_PROFILE].fly_api_key.as_deref(),
+            Some("key")
+        );
+    }
 }
@@ -0,0 +1,234 @@
+use super::config::read_config;
+use super::error::SpsdError;
+use super::types::{ConnectorKind, Instance, PortForward};
+use std::env;
+use std::io;
+use std::path::Path;
+use tokio::process::Command;
+
+pub enum Direction {
+    Upload,
+    Download,
+}
+
+// a copy between a local path and a path on the instance
+pub struct Transfer {
+    pub direction: Direction,
+    pub local: String,
+    pub remote: String,
+    pub recursive: bool,
+}
+
+// a way of opening a shell on, or copying files to and from, an instance's machine
+pub trait Connector {
+    fn console(&self, instance: &Instance) -> Command;
+
+    // runs a command without a terminal, its output going straight to spsd's stdout and stderr
+    fn exec(&self, instance: &Instance, command: &[String]) -> Command;
+
+    fn copy(&self, instance: &Instance, transfer: &Transfer) -> Result<Command, SpsdError>;
+
+    // a tunnel over the private network that stays open until the command exits
+    fn forward(&self, instance: &Instance, ports: PortForward) -> Command;
+
+    fn spawn_error(&self, command: &Command, error: io::Error) -> SpsdError {
+        match error.kind() {
+            io::ErrorKind::NotFound => SpsdError::CommandFailed(format!(
+                "{} is not installed or not on PATH",
+                command.as_std().get_program().to_string_lossy()
+            )),
+            _ => SpsdError::Io(error),
+        }
+    }
+}
+
+// connects through `flyctl ssh console`, which handles certificates and wireguard itself
+pub struct FlyctlConnector {
+    app_name: String,
+}
+
+impl Connector for FlyctlConnector {
+    fn console(&self, instance: &Instance) -> Command {
+        let mut command = Command::new("flyctl");
+        command
+            .arg("ssh")
+            .arg("console")
+            .arg("--machine")
+            .arg(&instance.machine_id)
+            .arg("--quiet")
+            .arg("-a")
+            .arg(&self.app_name);
+        command
+    }
+
+    // flyctl exits with the remote command's exit code
+    fn exec(&self, instance: &Instance, command: &[String]) -> Command {
+        let mut exec = Command::new("flyctl");
+        exec.arg("ssh")
+            .arg("console")
+            .arg("--machine")
+            .arg(&instance.machine_id)
+            .arg("--quiet")
+            .arg("-a")
+            .arg(&self.app_name)
+            .arg("-C")
+            .arg(shell_words::join(command));
+        exec
+    }
+
+    // sftp has no resume or progress, and can only copy directories down
+    fn copy(&self, instance: &Instance, transfer: &Transfer) -> Result<Command, SpsdError> {
+        let mut command = Command::new("flyctl");
+        command.arg("ssh").arg("sftp");
+        match transfer.direction {
+            Direction::Upload if transfer.recursive || Path::new(&transfer.local).is_dir() => {
+                return Err(SpsdError::Validation(format!(
+                    "The flyctl connector can't upload directories like {}, use --connector ssh \
+                     (or set SPSD_CONNECTOR=ssh) to copy them with rsync",
+                    transfer.local
+                )));
+            }
+            Direction::Upload => {
+                command
+                    .arg("put")
+                    .arg(&transfer.local)
+                    .arg(&transfer.remote);
+            }
+            Direction::Download => {
+                command.arg("get");
+                if transfer.recursive {
+                    command.arg("--recursive");
+                }
+                command.arg(&transfer.remote).arg(&transfer.local);
+            }
+        }
+        command
+            .arg("--machine")
+            .arg(&instance.machine_id)
+            .arg("-a")
+            .arg(&self.app_name);
+        Ok(command)
+    }
+
+    fn forward(&self, instance: &Instance, ports: PortForward) -> Command {
+        let mut command = Command::new("flyctl");
+        command
+            .arg("proxy")
+            .arg(format!("{}:{}", ports.local, ports.remote))
+            .arg(format!(
+                "{}.vm.{}.internal",
+                instance.machine_id, self.app_name
+            ))
+            .arg("-a")
+            .arg(&self.app_name);
+        command
+    }
+
+    fn spawn_error(&self, _command: &Command, error: io::Error) -> SpsdError {
+        SpsdError::from_flyctl(error)
+    }
+}
+
+// connects with the system ssh client to the machine's private (6PN) address, which must be
+// reachable (e.g. through a fly wireguard peer) and accept the configured key
+pub struct SshConnector {
+    user: String,
+    identity: Option<String>,
+}
+
+impl Connector for SshConnector {
+    fn console(&self, instance: &Instance) -> Command {
+        let mut command = Command::new("ssh");
+        command.arg("-t");
+        if let Some(identity) = &self.identity {
+            command.arg("-i").arg(identity);
+        }
+        command
+            .arg("-o")
+            .arg("StrictHostKeyChecking=accept-new")
+            .arg(format!("{}@{}", self.user, instance.private_ip));
+        command
+    }
+
+    fn exec(&self, instance: &Instance, command: &[String]) -> Command {
+        let mut exec = Command::new("ssh");
+        if let Some(identity) = &self.identity {
+            exec.arg("-i").arg(identity);
+        }
+        exec.arg("-o")
+            .arg("StrictHostKeyChecking=accept-new")
+            .arg(format!("{}@{}", self.user, instance.private_ip))
+            .arg("--")
+            .arg(shell_words::join(command));
+        exec
+    }
+
+    // rsync keeps partially transferred files so an interrupted copy resumes where it stopped
+    fn copy(&self, instance: &Instance, transfer: &Transfer) -> Result<Command, SpsdError> {
+        let mut ssh = String::from("ssh -o StrictHostKeyChecking=accept-new");
+        if let Some(identity) = &self.identity {
+            ssh.push_str(&format!(" -i '{}'", identity));
+        }
+        let remote = format!(
+            "{}@[{}]:{}",
+            self.user, instance.private_ip, transfer.remote
+        );
+
+        let mut command = Command::new("rsync");
+        command
+            .arg("--partial")
+            .arg("--progress")
+            .arg("--times")
+            .arg("-e")
+            .arg(ssh);
+        if transfer.recursive {
+            command.arg("--recursive");
+        }
+        match transfer.direction {
+            Direction::Upload => command.arg(&transfer.local).arg(remote),
+            Direction::Download => command.arg(remote).arg(&transfer.local),
+        };
+        Ok(command)
+    }
+
+    fn forward(&self, instance: &Instance, ports: PortForward) -> Command {
+        let mut command = Command::new("ssh");
+        command
+            .arg("-N")
+            .arg("-L")
+            .arg(format!("{}:localhost:{}", ports.local, ports.remote));
+        if let Some(identity) = &self.identity {
+            command.arg("-i").arg(identity);
+        }
+        command
+            .arg("-o")
+            .arg("StrictHostKeyChecking=accept-new")
+            .arg(format!("{}@{}", self.user, instance.private_ip));
+        command
+    }
+}
+
+// picks the connector from the command line, then SPSD_CONNECTOR, then the config file
+pub fn get_connector(
+    kind: Option<ConnectorKind>,
+    app_name: &str,
+) -> Result<Box<dyn Connector>, SpsdError> {
+    let config = read_config()?;
+    let kind = match kind {
+        Some(kind) => kind,
+        None => match env::var("SPSD_CONNECTOR").ok().or(config.connector) {
+            Some(kind) => kind.parse().map_err(SpsdError::Validation)?,
+            None => ConnectorKind::Flyctl,
+        },
+    };
+
+    Ok(match kind {
+        ConnectorKind::Flyctl => Box::new(FlyctlConnector {
+            app_name: app_name.to_string(),
+        }),
+        ConnectorKind::Ssh => Box::new(SshConnector {
+            user: config.ssh_user.unwrap_or_else(|| String::from("root")),
+            identity: config.ssh_identity,
+        }),
+    })
+}
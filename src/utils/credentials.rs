@@ -0,0 +1,223 @@
+use super::config::{get_data_path, write_private_file};
+use super::error::SpsdError;
+use super::types::CredentialStore;
+use aes_gcm::aead::{Aead, KeyInit};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use hkdf::Hkdf;
+use keyring::Entry;
+use serde_json::{json, Value};
+use sha2::Sha256;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+const KEYRING_SERVICE: &str = "spsd";
+// used when there is no keyring (e.g. a headless linux box without a secret service)
+const CREDENTIALS_FILE: &str = "spsd_credentials.json";
+const KEY_INFO: &[u8] = b"spsd credentials";
+
+// stores the profile's api key in the system keyring, falling back to a file only the user can
+// read, encrypted with a key tied to this machine and user
+pub fn store_api_key(profile: &str, api_key: &str) -> Result<CredentialStore, SpsdError> {
+    let stored = Entry::new(KEYRING_SERVICE, profile).and_then(|entry| entry.set_password(api_key));
+    if stored.is_ok() {
+        return Ok(CredentialStore::Keyring);
+    }
+
+    let path = get_data_path(CREDENTIALS_FILE)?;
+    let secret = machine_secret()?;
+    let mut credentials = read_credentials(&path, &secret)?;
+    credentials.insert(profile.to_string(), api_key.to_string());
+    write_credentials(&path, &credentials, &secret)?;
+    Ok(CredentialStore::File)
+}
+
+pub fn load_api_key(profile: &str, store: CredentialStore) -> Result<String, SpsdError> {
+    match store {
+        CredentialStore::Keyring => {
+            let entry = Entry::new(KEYRING_SERVICE, profile).map_err(keyring_error)?;
+            entry.get_password().map_err(keyring_error)
+        }
+        CredentialStore::File => {
+            read_credentials(&get_data_path(CREDENTIALS_FILE)?, &machine_secret()?)?
+                .remove(profile)
+                .ok_or_else(|| SpsdError::ConfigMissing(String::from("FLY_API_KEY")))
+        }
+    }
+}
+
+fn keyring_error(error: keyring::Error) -> SpsdError {
+    match error {
+        keyring::Error::NoEntry => SpsdError::ConfigMissing(String::from("FLY_API_KEY")),
+        error => SpsdError::CommandFailed(format!(
+            "Could not read the API key from the system keyring: {}",
+            error
+        )),
+    }
+}
+
+// what the file's key is derived from: the os's id for this machine and the user's home, so a
+// copy of the file (e.g. in a backup) is useless elsewhere. it doesn't keep the key from other
+// programs running as the same user, which is what the keyring is for
+fn machine_secret() -> Result<Vec<u8>, SpsdError> {
+    let home = dirs::home_dir().unwrap_or_default();
+    let machine_id = machine_id().ok_or_else(|| {
+        SpsdError::CommandFailed(String::from(
+            "No system keyring is available and this machine's id could not be read to encrypt the API key with. Set FLY_API_TOKEN instead",
+        ))
+    })?;
+    Ok(format!("{}\n{}", machine_id, home.display()).into_bytes())
+}
+
+#[cfg(target_os = "linux")]
+fn machine_id() -> Option<String> {
+    ["/etc/machine-id", "/var/lib/dbus/machine-id"]
+        .iter()
+        .filter_map(|path| fs::read_to_string(path).ok())
+        .map(|id| id.trim().to_string())
+        .find(|id| !id.is_empty())
+}
+
+#[cfg(target_os = "macos")]
+fn machine_id() -> Option<String> {
+    let output = std::process::Command::new("ioreg")
+        .args(["-rd1", "-c", "IOPlatformExpertDevice"])
+        .output()
+        .ok()?;
+    // `"IOPlatformUUID" = "..."`
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .find(|line| line.contains("IOPlatformUUID"))?
+        .split('"')
+        .nth(3)
+        .map(String::from)
+}
+
+#[cfg(windows)]
+fn machine_id() -> Option<String> {
+    let output = std::process::Command::new("reg")
+        .args([
+            "query",
+            r"HKLM\SOFTWARE\Microsoft\Cryptography",
+            "/v",
+            "MachineGuid",
+        ])
+        .output()
+        .ok()?;
+    // `    MachineGuid    REG_SZ    ...`
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .find(|line| line.contains("MachineGuid"))?
+        .split_whitespace()
+        .last()
+        .map(String::from)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+fn machine_id() -> Option<String> {
+    None
+}
+
+fn cipher(secret: &[u8], salt: &[u8]) -> Aes256Gcm {
+    let mut key = [0u8; 32];
+    // 32 bytes is well within what hkdf-sha256 can expand to
+    Hkdf::<Sha256>::new(Some(salt), secret)
+        .expand(KEY_INFO, &mut key)
+        .expect("valid key length");
+    Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key))
+}
+
+fn random_bytes<const N: usize>() -> Result<[u8; N], SpsdError> {
+    let mut bytes = [0u8; N];
+    getrandom::getrandom(&mut bytes).map_err(|error| {
+        SpsdError::CommandFailed(format!("Could not generate an encryption key: {}", error))
+    })?;
+    Ok(bytes)
+}
+
+fn decode(file: &Value, field: &str) -> Option<Vec<u8>> {
+    STANDARD.decode(file[field].as_str()?).ok()
+}
+
+fn read_credentials(path: &Path, secret: &[u8]) -> Result<BTreeMap<String, String>, SpsdError> {
+    if !path.exists() {
+        return Ok(BTreeMap::new());
+    }
+    let file: Value = serde_json::from_str(&fs::read_to_string(path)?)?;
+
+    let unreadable = || {
+        SpsdError::CommandFailed(format!(
+            "Could not decrypt {}, it was written on another machine or by another user. Add the profile again with `spsd profile add`",
+            path.display()
+        ))
+    };
+    let (salt, nonce, ciphertext) = match (
+        decode(&file, "salt"),
+        decode(&file, "nonce"),
+        decode(&file, "ciphertext"),
+    ) {
+        (Some(salt), Some(nonce), Some(ciphertext)) if nonce.len() == 12 => {
+            (salt, nonce, ciphertext)
+        }
+        _ => return Err(unreadable()),
+    };
+    let plaintext = cipher(secret, &salt)
+        .decrypt(Nonce::from_slice(&nonce), ciphertext.as_slice())
+        .map_err(|_| unreadable())?;
+    Ok(serde_json::from_slice(&plaintext)?)
+}
+
+fn write_credentials(
+    path: &Path,
+    credentials: &BTreeMap<String, String>,
+    secret: &[u8],
+) -> Result<(), SpsdError> {
+    let salt = random_bytes::<16>()?;
+    let nonce = random_bytes::<12>()?;
+    let ciphertext = cipher(secret, &salt)
+        .encrypt(
+            Nonce::from_slice(&nonce),
+            serde_json::to_vec(credentials)?.as_slice(),
+        )
+        .map_err(|_| SpsdError::CommandFailed(String::from("Could not encrypt the API key")))?;
+    let file = json!({
+        "salt": STANDARD.encode(salt),
+        "nonce": STANDARD.encode(nonce),
+        "ciphertext": STANDARD.encode(ciphertext),
+    });
+    write_private_file(path, &serde_json::to_string_pretty(&file)?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn credentials_file_is_encrypted_and_only_readable_by_the_user() {
+        let path =
+            std::env::temp_dir().join(format!("spsd_credentials_{}.json", std::process::id()));
+        let mut credentials = BTreeMap::new();
+        credentials.insert(String::from("work"), String::from("fo1_secret"));
+        write_credentials(&path, &credentials, b"machine\n/home/dev").unwrap();
+
+        assert!(!fs::read_to_string(&path).unwrap().contains("fo1_secret"));
+        assert_eq!(
+            read_credentials(&path, b"machine\n/home/dev").unwrap(),
+            credentials
+        );
+        // a copy of the file is no use on another machine
+        assert!(matches!(
+            read_credentials(&path, b"other\n/home/dev"),
+            Err(SpsdError::CommandFailed(_))
+        ));
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        fs::remove_file(path).unwrap();
+    }
+}
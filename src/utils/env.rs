@@ -0,0 +1,80 @@
+use super::error::SpsdError;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+// splits KEY=VALUE. errors never include the value, which may be a secret
+pub fn parse_env_var(pair: &str) -> Result<(String, String), String> {
+    let (key, value) = pair
+        .split_once('=')
+        .ok_or_else(|| String::from("Environment variables must be given as KEY=VALUE"))?;
+    let valid_key = key
+        .chars()
+        .next()
+        .is_some_and(|first| first.is_ascii_alphabetic() || first == '_')
+        && key
+            .chars()
+            .all(|char| char.is_ascii_alphanumeric() || char == '_');
+    if !valid_key {
+        return Err(format!("Invalid environment variable name {}", key));
+    }
+    Ok((key.to_string(), value.to_string()))
+}
+
+// reads a dotenv style file of KEY=VALUE lines, which may start with `export` and be quoted
+pub fn read_env_file(path: &Path) -> Result<BTreeMap<String, String>, SpsdError> {
+    let contents = fs::read_to_string(path).map_err(|error| {
+        SpsdError::Validation(format!("Could not read {}: {}", path.display(), error))
+    })?;
+
+    let mut env = BTreeMap::new();
+    for (number, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let (key, value) = parse_env_var(line).map_err(|error| {
+            SpsdError::Validation(format!("{}:{}: {}", path.display(), number + 1, error))
+        })?;
+        env.insert(key, unquote(value.trim()).to_string());
+    }
+    Ok(env)
+}
+
+fn unquote(value: &str) -> &str {
+    for quote in ['"', '\''] {
+        if let Some(inner) = value
+            .strip_prefix(quote)
+            .and_then(|value| value.strip_suffix(quote))
+        {
+            return inner;
+        }
+    }
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn env_file_is_parsed_and_errors_hide_values() {
+        let path = std::env::temp_dir().join(format!("spsd-env-test-{}", std::process::id()));
+        fs::write(
+            &path,
+            "# comment\n\nexport EDITOR=vim\nTOKEN=\"s3cr3t=\"\nEMPTY=\n",
+        )
+        .unwrap();
+        let env = read_env_file(&path).unwrap();
+        assert_eq!(env["EDITOR"], "vim");
+        assert_eq!(env["TOKEN"], "s3cr3t=");
+        assert_eq!(env["EMPTY"], "");
+
+        fs::write(&path, "1TOKEN=s3cr3t\n").unwrap();
+        let error = read_env_file(&path).unwrap_err().to_string();
+        fs::remove_file(&path).unwrap();
+        assert!(error.ends_with(":1: Invalid environment variable name 1TOKEN"));
+        assert!(!error.contains("s3cr3t"));
+    }
+}
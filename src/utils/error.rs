@@ -0,0 +1,149 @@
+use reqwest::header::InvalidHeaderValue;
+use std::{error::Error, fmt, io};
+
+#[derive(Debug)]
+pub enum SpsdError {
+    Auth(String),
+    NotFound(String),
+    Api {
+        status: u16,
+        message: String,
+    },
+    ConfigMissing(String),
+    FlyctlMissing,
+    CommandFailed(String),
+    // a command run on an instance exited with a non-zero code, which spsd exits with too
+    RemoteExit(i32),
+    Validation(String),
+    Rollback {
+        source: Box<SpsdError>,
+        cleaned_up: Vec<String>,
+        leaked: Vec<String>,
+    },
+    Io(io::Error),
+    Http(reqwest::Error),
+    Parse(serde_json::Error),
+}
+
+impl SpsdError {
+    // maps a failure to spawn flyctl onto FlyctlMissing when the binary isn't on PATH
+    pub fn from_flyctl(error: io::Error) -> Self {
+        match error.kind() {
+            io::ErrorKind::NotFound => SpsdError::FlyctlMissing,
+            _ => SpsdError::Io(error),
+        }
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            SpsdError::Validation(_) => 2,
+            SpsdError::Auth(_) => 3,
+            SpsdError::NotFound(_) => 4,
+            SpsdError::Api { .. } => 5,
+            SpsdError::ConfigMissing(_) => 6,
+            SpsdError::FlyctlMissing => 7,
+            SpsdError::CommandFailed(_) => 8,
+            SpsdError::RemoteExit(code) => *code,
+            SpsdError::Rollback { source, .. } => source.exit_code(),
+            SpsdError::Io(_) | SpsdError::Http(_) | SpsdError::Parse(_) => 1,
+        }
+    }
+
+    pub fn hint(&self) -> Option<&'static str> {
+        match self {
+            SpsdError::Auth(_) => Some(
+                "Check that your API key is valid and run `spsd profile <API_KEY> <ALLOCATE_IP>` again",
+            ),
+            SpsdError::NotFound(_) => Some("Run `spsd list` to see your instances"),
+            SpsdError::Api { status: 422, .. } => {
+                Some("Fly enforces cpu/memory ratios that may render your configuration invalid")
+            }
+            SpsdError::Api { status, .. } if *status >= 500 => {
+                Some("Fly's API may be having issues, try again in a moment")
+            }
+            SpsdError::ConfigMissing(_) => Some(
+                "Run `spsd profile <API_KEY> <ALLOCATE_IP>` to configure spsd, or `spsd profile add` for a named profile",
+            ),
+            SpsdError::FlyctlMissing => {
+                Some("Install flyctl from https://fly.io/docs/hands-on/install-flyctl/")
+            }
+            SpsdError::Validation(_) => Some("Use -h to see valid values"),
+            SpsdError::Http(_) => Some("Check your network connection"),
+            SpsdError::Rollback { leaked, .. } if !leaked.is_empty() => Some(
+                "Delete the leftover resources from the fly.io dashboard to avoid being billed for them",
+            ),
+            SpsdError::Rollback { source, .. } => source.hint(),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for SpsdError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SpsdError::Auth(message) => write!(f, "Authentication failed: {}", message),
+            SpsdError::NotFound(message) => write!(f, "{}", message),
+            SpsdError::Api { status, message } => {
+                write!(f, "Fly API error ({}): {}", status, message)
+            }
+            SpsdError::ConfigMissing(key) => write!(f, "{} not set in config file", key),
+            SpsdError::FlyctlMissing => write!(f, "flyctl is not installed or not on PATH"),
+            SpsdError::CommandFailed(message) => write!(f, "{}", message),
+            SpsdError::RemoteExit(code) => write!(f, "Command exited with code {}", code),
+            SpsdError::Validation(message) => write!(f, "{}", message),
+            SpsdError::Rollback {
+                source,
+                cleaned_up,
+                leaked,
+            } => {
+                write!(f, "{}", source)?;
+                if !cleaned_up.is_empty() {
+                    write!(f, "\nCleaned up: {}", cleaned_up.join(", "))?;
+                }
+                if !leaked.is_empty() {
+                    write!(f, "\nCould not clean up: {}", leaked.join(", "))?;
+                }
+                Ok(())
+            }
+            SpsdError::Io(error) => write!(f, "{}", error),
+            SpsdError::Http(error) => write!(f, "Request failed: {}", error),
+            SpsdError::Parse(error) => write!(f, "Failed to parse response: {}", error),
+        }
+    }
+}
+
+impl Error for SpsdError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SpsdError::Io(error) => Some(error),
+            SpsdError::Http(error) => Some(error),
+            SpsdError::Parse(error) => Some(error),
+            SpsdError::Rollback { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for SpsdError {
+    fn from(error: io::Error) -> Self {
+        SpsdError::Io(error)
+    }
+}
+
+impl From<reqwest::Error> for SpsdError {
+    fn from(error: reqwest::Error) -> Self {
+        SpsdError::Http(error)
+    }
+}
+
+impl From<serde_json::Error> for SpsdError {
+    fn from(error: serde_json::Error) -> Self {
+        SpsdError::Parse(error)
+    }
+}
+
+impl From<InvalidHeaderValue> for SpsdError {
+    fn from(_: InvalidHeaderValue) -> Self {
+        SpsdError::Validation(String::from("API key contains invalid characters"))
+    }
+}
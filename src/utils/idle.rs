@@ -0,0 +1,150 @@
+use super::types::{PortSpec, Protocol};
+use serde_json::{json, Value};
+
+pub const IDLE_TIMEOUT_KEY: &str = "spsd_idle_timeout";
+const RESTART_POLICY_KEY: &str = "spsd_restart_policy";
+const WATCHDOG_NAME: &str = "spsd-idle";
+// sh, -c, the script, its name, the timeout, the ports and how many of the arguments after them
+// are the image's entrypoint
+const WATCHDOG_ARGS: usize = 7;
+
+// runs the machine's original command in the background and exits (stopping the machine, since
+// the restart policy is "no") once there have been no ssh sessions, no connections on the exposed
+// tcp ports and no cpu load for the whole timeout. it runs on the machine itself, so it still fires
+// if the client that started the session disappears.
+const WATCHDOG_SCRIPT: &str = r#"timeout="$1"; exposed="$2"; shift 3
+"$@" &
+main=$!
+ports="0016"
+for port in $(echo "$exposed" | tr ',' ' '); do ports="$ports|$(printf '%04X' "$port")"; done
+idle=0
+while kill -0 "$main" 2>/dev/null; do
+  sleep 60
+  busy=0
+  if grep -qE "^ *[0-9]+: [0-9A-F]+:($ports) [0-9A-F]+:[0-9A-F]+ 01 " /proc/net/tcp /proc/net/tcp6 2>/dev/null; then busy=1; fi
+  case "$(cut -d ' ' -f 1 /proc/loadavg)" in 0.0*) ;; *) busy=1 ;; esac
+  if [ "$busy" -eq 1 ]; then idle=0; else idle=$((idle + 60)); fi
+  if [ "$idle" -ge "$timeout" ]; then kill "$main"; exit 0; fi
+done
+"#;
+
+// whether the machine runs the ENTRYPOINT of its image, which the config doesn't include
+pub fn runs_image_entrypoint(config: &Value) -> bool {
+    let is_set = |field: &str| {
+        config["init"][field]
+            .as_array()
+            .is_some_and(|command| !without_watchdog_values(command).is_empty())
+    };
+    !is_set("exec") && !is_set("entrypoint")
+}
+
+// wraps the machine's init command with the idle watchdog. machines running the image's own
+// command have no exec, so the watchdog goes in front of the entrypoint and the cmd still ends up
+// in "$@". setting an entrypoint replaces the image's, so `image_entrypoint` (see
+// runs_image_entrypoint) is run by the watchdog in its place
+pub fn install_watchdog(
+    config: &mut Value,
+    timeout_secs: u64,
+    ports: &[PortSpec],
+    image_entrypoint: &[String],
+) {
+    remove_watchdog(config);
+
+    let (field, image_entrypoint) = match config["init"]["exec"].as_array() {
+        Some(exec) if !exec.is_empty() => ("exec", &[][..]),
+        _ if runs_image_entrypoint(config) => ("entrypoint", image_entrypoint),
+        _ => ("entrypoint", &[][..]),
+    };
+    let mut wrapped = vec![
+        json!("/bin/sh"),
+        json!("-c"),
+        json!(WATCHDOG_SCRIPT),
+        json!(WATCHDOG_NAME),
+        json!(timeout_secs.to_string()),
+        json!(ports
+            .iter()
+            .filter(|port| port.protocol == Protocol::Tcp)
+            .map(|port| port.internal_port.to_string())
+            .collect::<Vec<_>>()
+            .join(",")),
+        json!(image_entrypoint.len().to_string()),
+    ];
+    wrapped.extend(image_entrypoint.iter().map(|arg| json!(arg)));
+    wrapped.extend(
+        config["init"][field]
+            .as_array()
+            .cloned()
+            .unwrap_or_default(),
+    );
+    config["init"][field] = Value::Array(wrapped);
+
+    let restart_policy = config["restart"]["policy"]
+        .as_str()
+        .unwrap_or_default()
+        .to_string();
+    if !config["metadata"].is_object() {
+        config["metadata"] = json!({});
+    }
+    config["metadata"][IDLE_TIMEOUT_KEY] = json!(timeout_secs.to_string());
+    config["metadata"][RESTART_POLICY_KEY] = json!(restart_policy);
+    config["restart"] = json!({ "policy": "no" });
+}
+
+pub fn idle_timeout(config: &Value) -> Option<u64> {
+    config["metadata"][IDLE_TIMEOUT_KEY].as_str()?.parse().ok()
+}
+
+// restores the init command and restart policy the watchdog replaced
+pub fn remove_watchdog(config: &mut Value) {
+    let metadata = match config["metadata"].as_object_mut() {
+        Some(metadata) if metadata.contains_key(IDLE_TIMEOUT_KEY) => metadata,
+        _ => return,
+    };
+    metadata.remove(IDLE_TIMEOUT_KEY);
+    let restart_policy = metadata
+        .remove(RESTART_POLICY_KEY)
+        .and_then(|policy| policy.as_str().map(String::from))
+        .unwrap_or_default();
+
+    if let Some(init) = config.get_mut("init").and_then(Value::as_object_mut) {
+        for field in ["exec", "entrypoint"] {
+            if let Some(command) = init.get_mut(field).and_then(Value::as_array_mut) {
+                let wrapped = command.len() - without_watchdog_values(command).len();
+                command.drain(..wrapped);
+                if command.is_empty() {
+                    init.remove(field);
+                }
+            }
+        }
+    }
+    match restart_policy.as_str() {
+        "" => {
+            if let Some(config) = config.as_object_mut() {
+                config.remove("restart");
+            }
+        }
+        policy => config["restart"] = json!({ "policy": policy }),
+    }
+}
+
+// the command the watchdog was wrapped around, or the command itself if there is none. an image
+// entrypoint the watchdog runs in place of the image isn't part of the machine's own command
+pub fn without_watchdog(command: &[String]) -> &[String] {
+    let args = |index: usize| command.get(index).map(String::as_str);
+    &command[watchdog_len(args(3), args(WATCHDOG_ARGS - 1), command.len())..]
+}
+
+fn without_watchdog_values(command: &[Value]) -> &[Value] {
+    let args = |index: usize| command.get(index).and_then(Value::as_str);
+    &command[watchdog_len(args(3), args(WATCHDOG_ARGS - 1), command.len())..]
+}
+
+fn watchdog_len(name: Option<&str>, image_entrypoint: Option<&str>, len: usize) -> usize {
+    if name != Some(WATCHDOG_NAME) {
+        return 0;
+    }
+    let image_entrypoint: usize = image_entrypoint
+        .and_then(|count| count.parse().ok())
+        .unwrap_or(0);
+    (WATCHDOG_ARGS + image_entrypoint).min(len)
+}
@@ -1,172 +1,1063 @@
-use super::request_utils::{self, get_headers, get_hostname};
-use super::types::{Instance, InstanceSpecs, Volume};
-use crate::utils::types::{Machine, Machines};
-use reqwest::{Client, Method};
-use serde::de::DeserializeOwned;
+use super::client::FlyClient;
+use super::error::SpsdError;
+use super::idle;
+use super::request_utils;
+use super::rollback::{Resource, Rollback};
+use super::types::{
+    parse_state, Instance, InstanceSpecs, InstanceState, MachineConfig, MachineOptions, PortSpec,
+    Protocol, Transition, TransitionCheck, WaitState,
+};
+use crate::cli::progress::Progress;
 use serde_json::Value;
-use std::error::Error;
-use tokio;
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
+use tokio::time::sleep;
 
-pub fn stop_machine(name: &str) -> Result<String, Box<dyn Error>> {
-    let instance_id = get_instance_from_name(&name)?.machine_id;
-    let hostname = get_hostname()? + "/machines/" + &instance_id + "/stop";
-    match make_request::<Value>(Method::POST, hostname, None) {
-        Ok(_) => Ok(instance_id),
-        Err(error) => Err(error),
+const IMAGE_PULL_TIMEOUT: Duration = Duration::from_secs(600);
+// how long starting, stopping or destroying a machine may take
+const STATE_CHANGE_TIMEOUT: Duration = Duration::from_secs(180);
+const WAIT_MAX_CALL_SECS: u64 = 60;
+const WAIT_INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+const WAIT_MAX_BACKOFF: Duration = Duration::from_secs(8);
+
+// stops the machine, returning false if it wasn't running
+pub async fn stop_machine(client: &FlyClient, instance: &Instance) -> Result<bool, SpsdError> {
+    if !ensure_transition(instance, Transition::Stop)? {
+        return Ok(false);
     }
+    client.stop_machine(&instance.machine_id).await?;
+    wait_for_state(
+        client,
+        &instance.machine_id,
+        None,
+        WaitState::Stopped,
+        STATE_CHANGE_TIMEOUT,
+    )
+    .await?;
+    Ok(true)
 }
 
-pub fn start_machine(name: &str) -> Result<String, Box<dyn Error>> {
-    let instance_id = get_instance_from_name(&name)?.machine_id;
-    let hostname = get_hostname()? + "/machines/" + &instance_id + "/start";
-    match make_request::<Value>(Method::POST, hostname, None) {
-        Ok(_) => {
-            poll_machine(&instance_id)?;
-            Ok(instance_id)
-        }
-        Err(error) => Err(error),
+// starts the machine, returning false if it was already running
+pub async fn start_machine(client: &FlyClient, instance: &Instance) -> Result<bool, SpsdError> {
+    if !ensure_transition(instance, Transition::Start)? {
+        return Ok(false);
+    }
+    // a watchdog left behind by an interrupted `sl` session would stop this machine unexpectedly
+    if instance.idle_timeout.is_some() {
+        remove_idle_timeout(client, instance).await?;
     }
+    client.start_machine(&instance.machine_id).await?;
+    wait_for_state(
+        client,
+        &instance.machine_id,
+        None,
+        WaitState::Started,
+        STATE_CHANGE_TIMEOUT,
+    )
+    .await?;
+    Ok(true)
 }
 
-pub fn create_volume(name: &str, volume_gb: u32, region: &str) -> Result<String, Box<dyn Error>> {
-    let hostname = get_hostname()? + "/volumes";
-    let body = serde_json::json!({"name" : name,
-                                      "region": region, 
-                                      "size_gb": volume_gb});
-    let volume = make_request::<Volume>(Method::POST, hostname, Some(body.to_string()))?;
-    if let Some(volume) = volume {
-        Ok(volume.id)
-    } else {
-        Err("Error in creation of volume".into())
+// starts the machine with a watchdog that stops it after `timeout_secs` without activity. one
+// that is already running is left as it is, and false returned
+pub async fn start_machine_with_idle_timeout(
+    client: &FlyClient,
+    instance: &Instance,
+    timeout_secs: u64,
+) -> Result<bool, SpsdError> {
+    if !ensure_transition(instance, Transition::Start)? {
+        return Ok(false);
     }
+    let mut config = client.get_machine_config(&instance.machine_id).await?;
+    let image_entrypoint = image_entrypoint_for(client, &config).await?;
+    idle::install_watchdog(
+        &mut config,
+        timeout_secs,
+        &instance.ports,
+        &image_entrypoint,
+    );
+    let machine = client
+        .update_machine(&instance.machine_id, config, false)
+        .await?;
+    wait_for_state(
+        client,
+        &machine.id,
+        Some(&machine.instance_id),
+        WaitState::Started,
+        STATE_CHANGE_TIMEOUT,
+    )
+    .await?;
+    Ok(true)
 }
 
-pub fn delete_volume(volume_id: &str) -> Result<String, Box<dyn Error>> {
-    let hostname = get_hostname()? + "/volumes/" + volume_id;
-    match make_request::<Value>(Method::DELETE, hostname, None) {
-        Ok(_) => Ok(String::from("Volume deleted")),
-        Err(error) => Err(error),
+// the image's ENTRYPOINT if the idle watchdog has to run it in the image's place. failing to look
+// it up fails the start, since the machine would run something else with the watchdog installed
+async fn image_entrypoint_for(
+    client: &FlyClient,
+    config: &Value,
+) -> Result<Vec<String>, SpsdError> {
+    if !idle::runs_image_entrypoint(config) {
+        return Ok(Vec::new());
     }
+    let image = config["image"].as_str().unwrap_or_default();
+    client.image_entrypoint(image).await.map_err(|error| {
+        SpsdError::CommandFailed(format!(
+            "Could not look up the ENTRYPOINT of image {} for the idle watchdog: {}. \
+             Pass --idle-timeout 0, or recreate the instance with --entrypoint",
+            image, error
+        ))
+    })
+}
+
+pub async fn remove_idle_timeout(client: &FlyClient, instance: &Instance) -> Result<(), SpsdError> {
+    let mut config = client.get_machine_config(&instance.machine_id).await?;
+    idle::remove_watchdog(&mut config);
+    client
+        .update_machine(&instance.machine_id, config, true)
+        .await?;
+    Ok(())
 }
 
-pub fn create_machine(
+// creates the volume and machine, then (with `wait`) lets the machine boot once so its image is
+// pulled before stopping it again. without `wait` the machine isn't launched at all, and its image
+// is pulled the first time it starts
+#[allow(clippy::too_many_arguments)]
+pub async fn create_machine(
+    client: &FlyClient,
     name: &str,
     image: &str,
-    cpu_count: u32,
-    memory_mb: u32,
-    volume_gb: u32,
+    specs: InstanceSpecs,
     region: &str,
-    port: Option<u16>,
-) -> Result<Instance, Box<dyn Error>> {
-    if port.is_some() {
-        ensure_port_is_unique(port)?;
-    }
-    let hostname = get_hostname()? + "/machines";
-    let volume_id = create_volume(name, volume_gb, region)?;
-    let body = request_utils::create_body_from_specs(
+    ports: &[PortSpec],
+    options: &MachineOptions,
+    progress: &Progress,
+    wait: bool,
+) -> Result<Instance, SpsdError> {
+    if !ports.is_empty() {
+        let instances = get_instances(client).await?;
+        ensure_ports_are_unique(&instances, ports, None)?;
+    }
+    let mut rollback = Rollback::new(client);
+    let result = provision_machine(
+        client,
+        &mut rollback,
         name,
         image,
-        InstanceSpecs {
-            cpu_count,
-            memory_mb,
-            volume_gb,
-        },
+        specs,
         region,
-        &volume_id,
-        port,
-    )?;
-    let machine = make_request::<Machine>(Method::POST, hostname, Some(body))?;
-    if let Some(instance) = machine {
-        let instance = request_utils::parse_response_body(vec![instance])?.remove(0);
-        poll_machine(&instance.machine_id)?;
-        stop_machine(&instance.name)?;
-        Ok(instance)
-    } else {
-        delete_volume(&volume_id)?;
-        Err("Error in instance creation".into())
-    }
-}
-
-pub fn delete_machine(name: &str) -> Result<String, Box<dyn Error>> {
-    let instance = get_instance_from_name(&name)?;
-    let hostname = get_hostname()? + "/machines/" + &instance.machine_id;
-    let result = make_request::<Value>(Method::DELETE, hostname, None)?;
-    if let Some(_) = result {
-        delete_volume(&instance.volume_id)?;
-        Ok(String::from("Deleted"))
-    } else {
-        Err("Did not delete".into())
-    }
-}
-
-pub fn get_instances() -> Result<Vec<Instance>, Box<dyn Error>> {
-    let hostname = get_hostname()? + "/machines";
-    let machines = make_request::<Machines>(Method::GET, hostname, None)?;
-    let instances = request_utils::parse_response_body(machines.unwrap());
-    instances
+        ports,
+        options,
+        None,
+        progress,
+        wait,
+    )
+    .await;
+    match result {
+        Ok(instance) => Ok(instance),
+        Err(error) => Err(rollback.undo(error).await),
+    }
 }
 
-#[tokio::main]
-async fn make_request<T: DeserializeOwned>(
-    method: Method,
-    hostname: String,
-    body: Option<String>,
-) -> Result<Option<T>, Box<dyn Error>> {
-    let headers = get_headers()?;
-    let client = Client::new();
-    let mut request = client.request(method, &hostname).headers(headers);
-
-    if let Some(b) = body {
-        request = request.body(b);
-    }
-
-    let response = request.send().await?;
-    let success = response.status().is_success();
-    let response_body = response.text().await?;
-    if success {
-        let parsed_response: T = serde_json::from_str(&response_body)?;
-        Ok(Some(parsed_response))
-    } else {
-        let parsed_response: Result<Value, serde_json::Error> =
-            serde_json::from_str(&response_body);
-
-        match parsed_response {
-            Ok(value) => {
-                if let Some(error_message) = value.get("error").and_then(|v| v.as_str()) {
-                    Err(error_message.to_string().into())
-                } else {
-                    Err(response_body.into())
-                }
+// creates a machine configured like `source`, mounted on a fork of its volume
+pub async fn clone_machine(
+    client: &FlyClient,
+    source: &Instance,
+    options: &MachineOptions,
+    name: &str,
+    region: &str,
+    ports: &[PortSpec],
+    progress: &Progress,
+) -> Result<Instance, SpsdError> {
+    let instances = get_instances(client).await?;
+    if instances.iter().any(|instance| instance.name == name) {
+        return Err(SpsdError::Validation(format!(
+            "Instance {} already exists",
+            name
+        )));
+    }
+    ensure_ports_are_unique(&instances, ports, None)?;
+
+    let mut rollback = Rollback::new(client);
+    let result = provision_machine(
+        client,
+        &mut rollback,
+        name,
+        &source.image,
+        source.specs.clone(),
+        region,
+        ports,
+        options,
+        Some(&source.volume_id),
+        progress,
+        true,
+    )
+    .await;
+    match result {
+        Ok(instance) => Ok(instance),
+        Err(error) => Err(rollback.undo(error).await),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn provision_machine(
+    client: &FlyClient,
+    rollback: &mut Rollback<'_>,
+    name: &str,
+    image: &str,
+    specs: InstanceSpecs,
+    region: &str,
+    ports: &[PortSpec],
+    options: &MachineOptions,
+    fork_from: Option<&str>,
+    progress: &Progress,
+    wait: bool,
+) -> Result<Instance, SpsdError> {
+    let volume = match fork_from {
+        Some(source_volume_id) => {
+            progress
+                .step(
+                    "Forking volume",
+                    client.fork_volume(name, specs.volume_gb, region, source_volume_id),
+                )
+                .await?
+        }
+        None => {
+            progress
+                .step(
+                    "Creating volume",
+                    client.create_volume(name, specs.volume_gb, region),
+                )
+                .await?
+        }
+    };
+    rollback.track(Resource::Volume(volume.id.clone()));
+
+    let mut body = request_utils::create_body_from_specs(
+        name, image, specs, region, &volume.id, ports, options,
+    );
+    // a launched machine would run (and bill) until something stops it
+    if !wait {
+        body["skip_launch"] = serde_json::json!(true);
+    }
+    let machine = progress
+        .step("Creating machine", client.create_machine(body))
+        .await?;
+    rollback.track(Resource::Machine(machine.id.clone()));
+
+    let instance_id = machine.instance_id.clone();
+    let mut instance = request_utils::parse_response_body(&[machine])?.remove(0);
+    if !wait {
+        return Ok(instance);
+    }
+    progress
+        .step(
+            "Pulling image",
+            wait_for_image(client, &instance.machine_id),
+        )
+        .await?;
+    progress
+        .step(
+            "Booting",
+            wait_for_state(
+                client,
+                &instance.machine_id,
+                Some(&instance_id),
+                WaitState::Started,
+                STATE_CHANGE_TIMEOUT,
+            ),
+        )
+        .await?;
+    instance.state = InstanceState::Started;
+    progress
+        .step("Stopping", stop_machine(client, &instance))
+        .await?;
+    instance.state = InstanceState::Stopped;
+    Ok(instance)
+}
+
+// whether `transition` has to be made, refusing the ones fly wouldn't accept
+fn ensure_transition(instance: &Instance, transition: Transition) -> Result<bool, SpsdError> {
+    match instance.state.check(transition) {
+        TransitionCheck::Allowed => Ok(true),
+        TransitionCheck::Redundant => Ok(false),
+        TransitionCheck::Refused => Err(SpsdError::Validation(format!(
+            "Cannot {} instance {} while it is {}",
+            transition, instance.name, instance.state
+        ))),
+    }
+}
+
+// machines stay "created" while fly pulls their image
+async fn wait_for_image(client: &FlyClient, machine_id: &str) -> Result<(), SpsdError> {
+    let started = Instant::now();
+    while parse_state(&client.get_machine(machine_id).await?.state) == InstanceState::Created {
+        if started.elapsed() > IMAGE_PULL_TIMEOUT {
+            return Err(SpsdError::CommandFailed(format!(
+                "Image was not pulled within {} seconds",
+                IMAGE_PULL_TIMEOUT.as_secs()
+            )));
+        }
+        sleep(Duration::from_secs(1)).await;
+    }
+    Ok(())
+}
+
+pub async fn delete_machine(client: &FlyClient, instance: &Instance) -> Result<String, SpsdError> {
+    ensure_transition(instance, Transition::Destroy)?;
+    destroy_machine(client, &instance.machine_id, false).await?;
+    client.delete_volume(&instance.volume_id).await?;
+    Ok(String::from("Deleted"))
+}
+
+// deletes the machine and waits until it's gone. the volume it mounted can only be deleted once
+// nothing has it mounted anymore
+pub async fn destroy_machine(
+    client: &FlyClient,
+    machine_id: &str,
+    force: bool,
+) -> Result<(), SpsdError> {
+    client.delete_machine(machine_id, force).await?;
+    wait_for_state(
+        client,
+        machine_id,
+        None,
+        WaitState::Destroyed,
+        STATE_CHANGE_TIMEOUT,
+    )
+    .await
+}
+
+// applies `update` to the machine's config, keeping it stopped if it isn't running
+pub async fn update_machine_config<F>(
+    client: &FlyClient,
+    instance: &Instance,
+    update: F,
+) -> Result<Instance, SpsdError>
+where
+    F: FnOnce(&mut Value),
+{
+    ensure_transition(instance, Transition::Update)?;
+    let mut config = client.get_machine_config(&instance.machine_id).await?;
+    update(&mut config);
+    let running = instance.state.is_running();
+    let machine = client
+        .update_machine(&instance.machine_id, config, !running)
+        .await?;
+    if running {
+        wait_for_state(
+            client,
+            &machine.id,
+            Some(&machine.instance_id),
+            WaitState::Started,
+            STATE_CHANGE_TIMEOUT,
+        )
+        .await?;
+    }
+
+    let mut updated = request_utils::parse_response_body(&[machine])?.remove(0);
+    updated.specs.volume_gb = instance.specs.volume_gb;
+    updated.state = instance.state;
+    Ok(updated)
+}
+
+// changes what spsd manages on an existing machine, leaving env, files and init alone without `options`
+pub async fn update_instance_specs(
+    client: &FlyClient,
+    instance: &Instance,
+    image: &str,
+    specs: &InstanceSpecs,
+    ports: &[PortSpec],
+    options: Option<&MachineOptions>,
+) -> Result<Instance, SpsdError> {
+    // an active watchdog has to wrap the new command, which may be the new image's entrypoint
+    let image_entrypoint = match options {
+        Some(options) if instance.idle_timeout.is_some() => {
+            let mut config = serde_json::json!({ "image": image });
+            request_utils::set_init(&mut config, &options.init);
+            image_entrypoint_for(client, &config).await?
+        }
+        _ => Vec::new(),
+    };
+    update_machine_config(client, instance, |config| {
+        config["image"] = Value::from(image);
+        request_utils::set_guest(config, specs);
+        request_utils::set_services(config, ports);
+        if let Some(options) = options {
+            request_utils::set_env(config, &options.env);
+            request_utils::set_files(config, &options.files);
+            let idle_timeout = idle::idle_timeout(config);
+            request_utils::set_init(config, &options.init);
+            if let Some(timeout_secs) = idle_timeout {
+                idle::install_watchdog(config, timeout_secs, ports, &image_entrypoint);
             }
-            Err(_) => Err(response_body.into()),
         }
+    })
+    .await
+}
+
+// grows the instance's volume, returning whether the machine has to restart to see the new size
+pub async fn extend_volume(
+    client: &FlyClient,
+    instance: &Instance,
+    volume_gb: u32,
+) -> Result<bool, SpsdError> {
+    if volume_gb < instance.specs.volume_gb {
+        return Err(SpsdError::Validation(format!(
+            "Volumes can't be shrunk, {} already has {} gb",
+            instance.name, instance.specs.volume_gb
+        )));
+    }
+    if volume_gb == instance.specs.volume_gb {
+        return Ok(false);
     }
+    let extension = client.extend_volume(&instance.volume_id, volume_gb).await?;
+    Ok(extension.needs_restart)
+}
+
+pub async fn get_instances(client: &FlyClient) -> Result<Vec<Instance>, SpsdError> {
+    Ok(get_instances_with_config(client)
+        .await?
+        .into_iter()
+        .map(|(instance, _)| instance)
+        .collect())
 }
 
-fn poll_machine(machine_id: &str) -> Result<String, Box<dyn Error>> {
-    let hostname = get_hostname()? + "/machines/" + machine_id + "/wait";
-    let body = serde_json::json!({"state" : "started"}).to_string();
-    if let Ok(_) = make_request::<Value>(Method::GET, hostname, Some(body)) {
-        Ok(String::from("Instance started"))
-    } else {
-        Err("Instance was not started".into())
+pub async fn get_instances_with_config(
+    client: &FlyClient,
+) -> Result<Vec<(Instance, MachineConfig)>, SpsdError> {
+    let (machines, volumes) = tokio::try_join!(client.list_machines(), client.list_volumes())?;
+    let mut instances = request_utils::parse_response_body(&machines)?;
+    // mounts only record the size a volume was created with, so prefer the live volume size
+    for instance in instances.iter_mut() {
+        if let Some(volume) = volumes
+            .iter()
+            .find(|volume| volume.id == instance.volume_id)
+        {
+            instance.specs.volume_gb = volume.size_gb;
+        }
     }
+    Ok(instances
+        .into_iter()
+        .zip(machines.into_iter().map(|machine| machine.config))
+        .collect())
 }
 
-fn get_instance_from_name(name: &str) -> Result<Instance, Box<dyn Error>> {
-    let instances = get_instances()?;
-    let instance = instances
-        .iter()
+pub async fn get_instance(client: &FlyClient, name: &str) -> Result<Instance, SpsdError> {
+    let instances = get_instances(client).await?;
+    instances
+        .into_iter()
         .find(|instance| instance.name == name)
-        .cloned();
+        .ok_or_else(|| SpsdError::NotFound(format!("Instance {} not found", name)))
+}
+
+// waits until the machine reaches `state`, giving up after `timeout`. fly's wait endpoint gives up
+// after at most a minute, so it is called again (with backoff) until `timeout` has passed.
+// `instance_id` pins the wait to a specific version of the machine, e.g. the one an update created
+pub async fn wait_for_state(
+    client: &FlyClient,
+    machine_id: &str,
+    instance_id: Option<&str>,
+    state: WaitState,
+    timeout: Duration,
+) -> Result<(), SpsdError> {
+    let started = Instant::now();
+    let mut backoff = WAIT_INITIAL_BACKOFF;
+    loop {
+        let remaining = timeout.saturating_sub(started.elapsed());
+        let call_timeout = remaining.as_secs().clamp(1, WAIT_MAX_CALL_SECS);
+        match client
+            .wait_machine(machine_id, instance_id, state, call_timeout)
+            .await
+        {
+            Ok(_) => return Ok(()),
+            Err(SpsdError::Api { status: 408, .. }) => {}
+            // fly didn't answer within the call's own timeout, so ask again
+            Err(SpsdError::Http(error)) if error.is_timeout() => {}
+            Err(SpsdError::Api { status, message }) => {
+                return Err(SpsdError::Api {
+                    status,
+                    message: format!("Instance was not {}: {}", state, message),
+                })
+            }
+            Err(error) => return Err(error),
+        }
 
-    instance.ok_or_else(|| "Instance not found".into())
+        if started.elapsed() + backoff >= timeout {
+            return Err(SpsdError::CommandFailed(format!(
+                "Instance did not become {} within {} seconds",
+                state,
+                timeout.as_secs()
+            )));
+        }
+        sleep(backoff).await;
+        backoff = (backoff * 2).min(WAIT_MAX_BACKOFF);
+    }
 }
 
-fn ensure_port_is_unique(port: Option<u16>) -> Result<(), Box<dyn Error>> {
-    let instances = get_instances()?;
-    if instances.iter().any(|instance| instance.port == port) {
-        return Err("Instance port is not unique".into());
+// instances share the app's ip, so a public port and protocol can only be exposed once. checks
+// `ports` against each other and against every instance other than `except`
+pub fn ensure_ports_are_unique(
+    instances: &[Instance],
+    ports: &[PortSpec],
+    except: Option<&str>,
+) -> Result<(), SpsdError> {
+    let taken: HashSet<(u16, Protocol)> = instances
+        .iter()
+        .filter(|instance| Some(instance.name.as_str()) != except)
+        .flat_map(|instance| instance.ports.iter())
+        .map(|port| (port.public_port, port.protocol))
+        .collect();
+    let mut seen = HashSet::new();
+    for port in ports {
+        let key = (port.public_port, port.protocol);
+        if taken.contains(&key) || !seen.insert(key) {
+            return Err(SpsdError::Validation(format!(
+                "Instance port {} is not unique",
+                port.public_port
+            )));
+        }
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::progress::Progress;
+    use crate::utils::mock_server::{
+        create_test_instance, MockServer, TestInstance, MOCK_APP_NAME,
+    };
+    use crate::utils::types::InitCommand;
+
+    fn specs(cpu_count: u32, memory_mb: u32, volume_gb: u32) -> InstanceSpecs {
+        InstanceSpecs {
+            cpu_kind: String::from("shared"),
+            cpu_count,
+            memory_mb,
+            volume_gb,
+        }
+    }
+
+    fn http(port: u16) -> Vec<PortSpec> {
+        vec![port.to_string().parse().unwrap()]
+    }
+
+    fn setup() -> (MockServer, FlyClient) {
+        let server = MockServer::start();
+        let client = server.client();
+        (server, client)
+    }
+
+    #[tokio::test]
+    async fn create_list_and_delete_machine() {
+        let (server, client) = setup();
+
+        let instance = create_test_instance(
+            &client,
+            "devbox",
+            TestInstance {
+                specs: specs(2, 512, 3),
+                ports: http(8080),
+                ..TestInstance::default()
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(instance.name, "devbox");
+        assert_eq!(server.volumes().len(), 1);
+
+        let instances = get_instances(&client).await.unwrap();
+        assert_eq!(instances.len(), 1);
+        assert_eq!(instances[0].volume_id, server.volumes()[0]["id"]);
+        assert_eq!(instances[0].specs.cpu_count, 2);
+        assert_eq!(instances[0].specs.memory_mb, 512);
+        assert_eq!(instances[0].specs.volume_gb, 3);
+        assert_eq!(instances[0].ports, http(8080));
+        assert_eq!(instances[0].state, InstanceState::Stopped);
+
+        delete_machine(&client, &instances[0]).await.unwrap();
+        assert!(get_instances(&client).await.unwrap().is_empty());
+        assert!(server.volumes().is_empty());
+    }
+
+    #[tokio::test]
+    async fn create_machine_rejects_duplicate_port() {
+        let (server, client) = setup();
+
+        create_test_instance(
+            &client,
+            "first",
+            TestInstance {
+                ports: http(8080),
+                ..TestInstance::default()
+            },
+        )
+        .await
+        .unwrap();
+        let result = create_test_instance(
+            &client,
+            "second",
+            TestInstance {
+                ports: http(8080),
+                ..TestInstance::default()
+            },
+        )
+        .await;
+
+        assert!(matches!(result, Err(SpsdError::Validation(_))));
+        assert_eq!(server.machines().len(), 1);
+        assert_eq!(server.volumes().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn multiple_ports_are_exposed_as_separate_services() {
+        let (server, client) = setup();
+        let ports: Vec<PortSpec> = ["443:8443/tls,http", "5432/tcp", "8080/udp"]
+            .iter()
+            .map(|port| port.parse().unwrap())
+            .collect();
+
+        create_test_instance(
+            &client,
+            "devbox",
+            TestInstance {
+                ports: ports.clone(),
+                ..TestInstance::default()
+            },
+        )
+        .await
+        .unwrap();
+
+        let services = &server.machines()[0]["config"]["services"];
+        assert_eq!(services.as_array().unwrap().len(), 3);
+        assert_eq!(services[0]["ports"][0]["port"], 443);
+        assert_eq!(services[0]["internal_port"], 8443);
+        assert_eq!(services[2]["protocol"], "udp");
+        let instance = get_instance(&client, "devbox").await.unwrap();
+        assert_eq!(instance.ports, ports);
+        let displayed: Vec<String> = instance.ports.iter().map(PortSpec::to_string).collect();
+        assert_eq!(displayed, ["443:8443/tls,http", "5432/tcp", "8080/udp"]);
+
+        // the same number over another protocol doesn't clash
+        create_test_instance(
+            &client,
+            "other",
+            TestInstance {
+                ports: http(8080),
+                ..TestInstance::default()
+            },
+        )
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn failed_machine_creation_removes_volume() {
+        let (server, client) = setup();
+        server.fail_next("POST", "/machines", 422);
+
+        let error = create_test_instance(
+            &client,
+            "devbox",
+            TestInstance {
+                specs: specs(16, 256, 1),
+                ..TestInstance::default()
+            },
+        )
+        .await
+        .unwrap_err();
+
+        match error {
+            SpsdError::Rollback {
+                source,
+                cleaned_up,
+                leaked,
+            } => {
+                assert!(matches!(*source, SpsdError::Api { status: 422, .. }));
+                assert_eq!(cleaned_up.len(), 1);
+                assert!(cleaned_up[0].starts_with("volume"));
+                assert!(leaked.is_empty());
+            }
+            error => panic!("unexpected error: {}", error),
+        }
+        assert!(server.volumes().is_empty());
+    }
+
+    #[tokio::test]
+    async fn failed_stop_removes_machine_then_volume() {
+        let (server, client) = setup();
+        server.fail_next("POST", "/stop", 500);
+
+        let error = create_test_instance(&client, "devbox", TestInstance::default())
+            .await
+            .unwrap_err();
+
+        match error {
+            SpsdError::Rollback {
+                cleaned_up, leaked, ..
+            } => {
+                assert_eq!(cleaned_up.len(), 2);
+                assert!(cleaned_up[0].starts_with("machine"));
+                assert!(cleaned_up[1].starts_with("volume"));
+                assert!(leaked.is_empty());
+            }
+            error => panic!("unexpected error: {}", error),
+        }
+        assert!(server.machines().is_empty());
+        assert!(server.volumes().is_empty());
+    }
+
+    #[tokio::test]
+    async fn rollback_reports_resources_it_could_not_remove() {
+        let (server, client) = setup();
+        server.fail_next("GET", "/wait", 500);
+        server.fail_next("DELETE", "", 500);
+
+        let error = create_test_instance(&client, "devbox", TestInstance::default())
+            .await
+            .unwrap_err();
+
+        match &error {
+            SpsdError::Rollback {
+                cleaned_up, leaked, ..
+            } => {
+                assert!(cleaned_up.is_empty());
+                assert_eq!(leaked.len(), 2);
+            }
+            error => panic!("unexpected error: {}", error),
+        }
+        assert!(error.to_string().contains("Could not clean up"));
+        assert_eq!(server.machines().len(), 1);
+        assert_eq!(server.volumes().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn create_without_wait_leaves_machine_unlaunched() {
+        let (server, client) = setup();
+        let instance = create_test_instance(
+            &client,
+            "devbox",
+            TestInstance {
+                wait: false,
+                ..TestInstance::default()
+            },
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(instance.state, InstanceState::Created);
+        assert_eq!(server.machines()[0]["state"], "created");
+        assert!(start_machine(&client, &instance).await.unwrap());
+        assert_eq!(server.machines()[0]["state"], "started");
+    }
+
+    #[tokio::test]
+    async fn invalid_transitions_are_refused() {
+        let (server, client) = setup();
+        let mut instance = create_test_instance(
+            &client,
+            "devbox",
+            TestInstance {
+                wait: false,
+                ..TestInstance::default()
+            },
+        )
+        .await
+        .unwrap();
+
+        instance.state = parse_state("destroying");
+        let error = start_machine(&client, &instance).await.unwrap_err();
+        assert!(matches!(error, SpsdError::Validation(_)));
+        assert_eq!(
+            error.to_string(),
+            "Cannot start instance devbox while it is destroying"
+        );
+        assert!(delete_machine(&client, &instance).await.is_err());
+
+        instance.state = parse_state("stopping");
+        assert!(start_machine(&client, &instance).await.is_err());
+        assert!(stop_machine(&client, &instance).await.unwrap());
+        assert_eq!(server.machines()[0]["state"], "stopped");
+
+        // failed machines have to be updated or recreated before they start again
+        instance.state = parse_state("launch_failed");
+        assert_eq!(
+            start_machine(&client, &instance)
+                .await
+                .unwrap_err()
+                .to_string(),
+            "Cannot start instance devbox while it is failed"
+        );
+        assert!(!stop_machine(&client, &instance).await.unwrap());
+        instance.state = parse_state("starting");
+        assert!(update_machine_config(&client, &instance, |_| {})
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn transitions_to_the_current_state_are_skipped() {
+        let (server, client) = setup();
+        let instance = create_test_instance(&client, "devbox", TestInstance::default())
+            .await
+            .unwrap();
+        assert_eq!(instance.state, InstanceState::Stopped);
+        // a stop request would fail if it reached the mock
+        server.fail_next("POST", "/stop", 500);
+        assert!(!stop_machine(&client, &instance).await.unwrap());
+
+        assert!(start_machine(&client, &instance).await.unwrap());
+        let instance = get_instance(&client, "devbox").await.unwrap();
+        server.fail_next("POST", "/start", 500);
+        assert!(!start_machine(&client, &instance).await.unwrap());
+        assert!(!start_machine_with_idle_timeout(&client, &instance, 600)
+            .await
+            .unwrap());
+        assert_eq!(
+            server.machines()[0]["config"]["init"]["exec"],
+            serde_json::json!(["/bin/sleep", "inf"])
+        );
+    }
+
+    #[tokio::test]
+    async fn wait_retries_until_the_state_is_reached_or_time_runs_out() {
+        let (server, client) = setup();
+        let instance = create_test_instance(
+            &client,
+            "devbox",
+            TestInstance {
+                wait: false,
+                ..TestInstance::default()
+            },
+        )
+        .await
+        .unwrap();
+        client.start_machine(&instance.machine_id).await.unwrap();
+
+        server.fail_next("GET", "/wait", 408);
+        server.fail_next("GET", "/wait", 408);
+        let timeout = Duration::from_secs(5);
+        wait_for_state(
+            &client,
+            &instance.machine_id,
+            None,
+            WaitState::Started,
+            timeout,
+        )
+        .await
+        .unwrap();
+
+        let error = wait_for_state(
+            &client,
+            &instance.machine_id,
+            None,
+            WaitState::Stopped,
+            Duration::from_secs(1),
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(error, SpsdError::CommandFailed(_)));
+    }
+
+    #[tokio::test]
+    async fn idle_watchdog_is_removed_on_next_start() {
+        let (server, client) = setup();
+        let instance = create_test_instance(
+            &client,
+            "devbox",
+            TestInstance {
+                ports: http(8080),
+                ..TestInstance::default()
+            },
+        )
+        .await
+        .unwrap();
+
+        start_machine_with_idle_timeout(&client, &instance, 600)
+            .await
+            .unwrap();
+        let config = &server.machines()[0]["config"];
+        assert_eq!(config["init"]["exec"][4], "600");
+        assert_eq!(config["init"]["exec"][5], "8080");
+        assert_eq!(config["restart"]["policy"], "no");
+
+        // the watchdog stops the instance once it's idle
+        let instance = get_instance(&client, "devbox").await.unwrap();
+        assert!(stop_machine(&client, &instance).await.unwrap());
+        let instance = get_instance(&client, "devbox").await.unwrap();
+        assert_eq!(instance.idle_timeout, Some(600));
+        assert!(start_machine(&client, &instance).await.unwrap());
+
+        let config = &server.machines()[0]["config"];
+        assert_eq!(
+            config["init"]["exec"],
+            serde_json::json!(["/bin/sleep", "inf"])
+        );
+        assert!(config.get("restart").is_none());
+        assert!(get_instance(&client, "devbox")
+            .await
+            .unwrap()
+            .idle_timeout
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn idle_watchdog_wraps_image_command() {
+        let (server, client) = setup();
+        server.set_image_entrypoint("library/ubuntu", &["/docker-entrypoint.sh"]);
+        let options = MachineOptions {
+            init: InitCommand::new(
+                None,
+                Some(vec![String::from("jupyter"), String::from("lab")]),
+                false,
+            ),
+            ..MachineOptions::default()
+        };
+        let instance = create_test_instance(
+            &client,
+            "devbox",
+            TestInstance {
+                ports: http(8888),
+                options: options.clone(),
+                ..TestInstance::default()
+            },
+        )
+        .await
+        .unwrap();
+        let config = &server.machines()[0]["config"];
+        assert!(config["init"].get("exec").is_none());
+        assert_eq!(config["init"]["cmd"], serde_json::json!(["jupyter", "lab"]));
+
+        start_machine_with_idle_timeout(&client, &instance, 600)
+            .await
+            .unwrap();
+        let config = &server.machines()[0]["config"];
+        // the image's entrypoint still runs, under the watchdog
+        let entrypoint = config["init"]["entrypoint"].as_array().unwrap();
+        assert_eq!(entrypoint[3], "spsd-idle");
+        assert_eq!(entrypoint[6], "1");
+        assert_eq!(entrypoint[7], "/docker-entrypoint.sh");
+        assert_eq!(entrypoint.len(), 8);
+        let (_, machine_config) = get_instances_with_config(&client).await.unwrap().remove(0);
+        assert_eq!(
+            MachineOptions::from_config(&machine_config).init,
+            options.init
+        );
+
+        let instance = get_instance(&client, "devbox").await.unwrap();
+        stop_machine(&client, &instance).await.unwrap();
+        let instance = get_instance(&client, "devbox").await.unwrap();
+        start_machine(&client, &instance).await.unwrap();
+        assert_eq!(
+            server.machines()[0]["config"]["init"],
+            serde_json::json!({ "cmd": ["jupyter", "lab"] })
+        );
+    }
+
+    #[tokio::test]
+    async fn resize_keeps_mount_and_only_grows_volume() {
+        let (server, client) = setup();
+        let instance = create_test_instance(
+            &client,
+            "devbox",
+            TestInstance {
+                specs: specs(1, 512, 3),
+                ..TestInstance::default()
+            },
+        )
+        .await
+        .unwrap();
+
+        let resized = specs(2, 1024, 3);
+        update_instance_specs(&client, &instance, "ubuntu", &resized, &http(8080), None)
+            .await
+            .unwrap();
+        extend_volume(&client, &instance, 10).await.unwrap();
+
+        let instance = get_instance(&client, "devbox").await.unwrap();
+        assert_eq!(instance.specs.cpu_count, 2);
+        assert_eq!(instance.specs.memory_mb, 1024);
+        assert_eq!(instance.specs.volume_gb, 10);
+        assert_eq!(instance.ports, http(8080));
+        assert_eq!(
+            server.machines()[0]["config"]["mounts"][0]["volume"],
+            server.volumes()[0]["id"]
+        );
+
+        let error = extend_volume(&client, &instance, 5).await.unwrap_err();
+        assert!(matches!(error, SpsdError::Validation(_)));
+        assert_eq!(server.volumes()[0]["size_gb"], 10);
+    }
+
+    #[tokio::test]
+    async fn clone_forks_volume_and_copies_config() {
+        let (server, client) = setup();
+        let mut options = MachineOptions::default();
+        options
+            .env
+            .insert(String::from("EDITOR"), String::from("vim"));
+        let source = create_test_instance(
+            &client,
+            "devbox",
+            TestInstance {
+                specs: specs(2, 1024, 5),
+                ports: http(8080),
+                options: options.clone(),
+                ..TestInstance::default()
+            },
+        )
+        .await
+        .unwrap();
+
+        let clone = clone_machine(
+            &client,
+            &source,
+            &options,
+            "devbox-2",
+            "fra",
+            &[],
+            &Progress::hidden(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(clone.image, source.image);
+        assert_eq!(clone.specs.cpu_count, 2);
+        assert_eq!(clone.region, "fra");
+        assert!(clone.ports.is_empty());
+        assert_eq!(server.machines()[1]["config"]["env"]["EDITOR"], "vim");
+        assert_eq!(server.volumes()[1]["source_volume_id"], source.volume_id);
+
+        let error = clone_machine(
+            &client,
+            &source,
+            &options,
+            "devbox-2",
+            "ams",
+            &[],
+            &Progress::hidden(),
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(error, SpsdError::Validation(_)));
+        assert_eq!(server.volumes().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn get_unknown_instance_fails() {
+        let (_server, client) = setup();
+
+        let error = get_instance(&client, "missing").await.unwrap_err();
+        assert!(matches!(error, SpsdError::NotFound(_)));
+        assert_eq!(error.to_string(), "Instance missing not found");
+    }
+
+    #[tokio::test]
+    async fn requests_are_rejected_with_wrong_api_key() {
+        let server = MockServer::start();
+        let client = server.client_for(MOCK_APP_NAME, "wrong");
+
+        let error = get_instances(&client).await.unwrap_err();
+        assert!(matches!(error, SpsdError::Auth(_)));
+        assert_eq!(error.exit_code(), 3);
+    }
+}
@@ -0,0 +1,332 @@
+use super::error::SpsdError;
+use super::types::{InitCommand, Instance, InstanceSpecs, MachineConfig, MachineOptions, PortSpec};
+use crate::cli::value_parsers::{
+    parse_cpu, parse_image, parse_memory, parse_port_spec, parse_region, parse_volume,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+pub const MANIFEST_FILE: &str = "spsd.toml";
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Manifest {
+    #[serde(default)]
+    instances: BTreeMap<String, InstanceDefinition>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct InstanceDefinition {
+    image: String,
+    cpus: u32,
+    memory: u32,
+    volume: u32,
+    region: String,
+    // same syntax as --port, e.g. "443:8443/tls,http"
+    #[serde(default)]
+    ports: Vec<String>,
+    #[serde(default)]
+    env: BTreeMap<String, String>,
+    // guest path to a local path, relative to the manifest
+    #[serde(default)]
+    files: BTreeMap<String, String>,
+    // without any of these the instance sleeps instead of running the image's command
+    entrypoint: Option<Vec<String>>,
+    cmd: Option<Vec<String>>,
+    #[serde(default)]
+    keep_image_cmd: bool,
+}
+
+// an instance as described by the manifest, validated and ready to be created
+pub struct DesiredInstance {
+    pub name: String,
+    pub image: String,
+    pub specs: InstanceSpecs,
+    pub region: String,
+    pub ports: Vec<PortSpec>,
+    pub options: MachineOptions,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PlanAction {
+    Create,
+    Update,
+    Unchanged,
+}
+
+#[derive(Serialize)]
+pub struct FieldChange {
+    pub field: &'static str,
+    pub current: String,
+    pub desired: String,
+}
+
+#[derive(Serialize)]
+pub struct PlanEntry {
+    pub name: String,
+    pub action: PlanAction,
+    // differences that can be applied to the existing machine
+    pub changes: Vec<FieldChange>,
+    // differences that can't be applied in place and are only reported
+    pub drift: Vec<FieldChange>,
+}
+
+pub fn load_manifest(path: &Path) -> Result<Vec<DesiredInstance>, SpsdError> {
+    let contents = fs::read_to_string(path).map_err(|error| {
+        SpsdError::Validation(format!("Could not read {}: {}", path.display(), error))
+    })?;
+    let manifest: Manifest = toml::from_str(&contents)
+        .map_err(|error| SpsdError::Validation(format!("Invalid {}: {}", path.display(), error)))?;
+    let base_dir = path.parent().unwrap_or_else(|| Path::new("."));
+
+    let mut ports = HashSet::new();
+    let mut instances = Vec::new();
+    for (name, definition) in manifest.instances {
+        let instance = validate_definition(&name, definition, base_dir)
+            .map_err(|error| SpsdError::Validation(format!("{}: {}", name, error)))?;
+        for port in &instance.ports {
+            if !ports.insert((port.public_port, port.protocol)) {
+                return Err(SpsdError::Validation(format!(
+                    "{}: port {} is used more than once",
+                    name, port.public_port
+                )));
+            }
+        }
+        instances.push(instance);
+    }
+    Ok(instances)
+}
+
+fn validate_definition(
+    name: &str,
+    definition: InstanceDefinition,
+    base_dir: &Path,
+) -> Result<DesiredInstance, String> {
+    let mut files = BTreeMap::new();
+    for (guest_path, local_path) in definition.files {
+        let contents = fs::read(base_dir.join(&local_path))
+            .map_err(|error| format!("Could not read {}: {}", local_path, error))?;
+        files.insert(guest_path, STANDARD.encode(contents));
+    }
+
+    Ok(DesiredInstance {
+        name: name.to_string(),
+        image: parse_image(&definition.image)?,
+        specs: InstanceSpecs {
+            cpu_kind: String::from("shared"),
+            cpu_count: parse_cpu(&definition.cpus.to_string())?,
+            memory_mb: parse_memory(&definition.memory.to_string())?,
+            volume_gb: parse_volume(&definition.volume.to_string())?,
+        },
+        region: parse_region(&definition.region)?,
+        ports: definition
+            .ports
+            .iter()
+            .map(|port| parse_port_spec(port))
+            .collect::<Result<_, _>>()?,
+        options: MachineOptions {
+            env: definition.env,
+            files,
+            init: InitCommand::new(
+                definition.entrypoint,
+                definition.cmd,
+                definition.keep_image_cmd,
+            ),
+        },
+    })
+}
+
+// compares the manifest against the instances that currently exist
+pub fn plan(desired: &[DesiredInstance], current: &[(Instance, MachineConfig)]) -> Vec<PlanEntry> {
+    desired
+        .iter()
+        .map(|desired| {
+            match current
+                .iter()
+                .find(|(instance, _)| instance.name == desired.name)
+            {
+                Some((instance, config)) => diff_instance(desired, instance, config),
+                None => PlanEntry {
+                    name: desired.name.clone(),
+                    action: PlanAction::Create,
+                    changes: Vec::new(),
+                    drift: Vec::new(),
+                },
+            }
+        })
+        .collect()
+}
+
+fn diff_instance(
+    desired: &DesiredInstance,
+    instance: &Instance,
+    config: &MachineConfig,
+) -> PlanEntry {
+    let mut changes = Vec::new();
+    compare(&mut changes, "image", &instance.image, &desired.image);
+    compare(
+        &mut changes,
+        "cpus",
+        &instance.specs.cpu_count,
+        &desired.specs.cpu_count,
+    );
+    compare(
+        &mut changes,
+        "memory",
+        &instance.specs.memory_mb,
+        &desired.specs.memory_mb,
+    );
+    compare(
+        &mut changes,
+        "ports",
+        &display_ports(&instance.ports),
+        &display_ports(&desired.ports),
+    );
+
+    // only keys are shown so values never end up in the output
+    let current = MachineOptions::from_config(config);
+    if current.env != desired.options.env {
+        changes.push(FieldChange {
+            field: "env",
+            current: join_keys(current.env.keys()),
+            desired: join_keys(desired.options.env.keys()),
+        });
+    }
+    if current.files != desired.options.files {
+        changes.push(FieldChange {
+            field: "files",
+            current: join_keys(current.files.keys()),
+            desired: join_keys(desired.options.files.keys()),
+        });
+    }
+    compare(&mut changes, "init", &current.init, &desired.options.init);
+
+    let mut drift = Vec::new();
+    compare(&mut drift, "region", &instance.region, &desired.region);
+    // volumes can be extended but never shrunk
+    let volume_changes = if desired.specs.volume_gb > instance.specs.volume_gb {
+        &mut changes
+    } else {
+        &mut drift
+    };
+    compare(
+        volume_changes,
+        "volume",
+        &instance.specs.volume_gb,
+        &desired.specs.volume_gb,
+    );
+
+    PlanEntry {
+        name: desired.name.clone(),
+        action: if changes.is_empty() {
+            PlanAction::Unchanged
+        } else {
+            PlanAction::Update
+        },
+        changes,
+        drift,
+    }
+}
+
+fn compare<T: PartialEq + ToString>(
+    changes: &mut Vec<FieldChange>,
+    field: &'static str,
+    current: &T,
+    desired: &T,
+) {
+    if current != desired {
+        changes.push(FieldChange {
+            field,
+            current: current.to_string(),
+            desired: desired.to_string(),
+        });
+    }
+}
+
+fn display_ports(ports: &[PortSpec]) -> String {
+    if ports.is_empty() {
+        return String::from("none");
+    }
+    ports
+        .iter()
+        .map(PortSpec::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn join_keys<'a>(keys: impl Iterator<Item = &'a String>) -> String {
+    let keys: Vec<&str> = keys.map(String::as_str).collect();
+    if keys.is_empty() {
+        String::from("none")
+    } else {
+        keys.join(", ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::machines::{get_instances_with_config, update_instance_specs};
+    use crate::utils::mock_server::{create_test_instance, MockServer, TestInstance};
+
+    fn desired(name: &str, cpu_count: u32, region: &str) -> DesiredInstance {
+        DesiredInstance {
+            name: name.to_string(),
+            image: String::from("ubuntu"),
+            specs: InstanceSpecs {
+                cpu_kind: String::from("shared"),
+                cpu_count,
+                memory_mb: 512,
+                volume_gb: 3,
+            },
+            region: region.to_string(),
+            ports: Vec::new(),
+            options: MachineOptions::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn plan_reports_changes_and_drift() {
+        let server = MockServer::start();
+        let client = server.client();
+        let existing = desired("devbox", 1, "ams");
+        let instance = TestInstance {
+            specs: existing.specs.clone(),
+            ..TestInstance::default()
+        };
+        create_test_instance(&client, &existing.name, instance)
+            .await
+            .unwrap();
+
+        let manifest = vec![desired("devbox", 2, "fra"), desired("scratch", 1, "ams")];
+        let current = get_instances_with_config(&client).await.unwrap();
+        let entries = plan(&manifest, &current);
+
+        assert_eq!(entries[0].action, PlanAction::Update);
+        assert_eq!(entries[0].changes.len(), 1);
+        assert_eq!(entries[0].changes[0].field, "cpus");
+        assert_eq!(entries[0].drift.len(), 1);
+        assert_eq!(entries[0].drift[0].field, "region");
+        assert_eq!(entries[1].action, PlanAction::Create);
+
+        let (instance, _) = &current[0];
+        let desired = &manifest[0];
+        update_instance_specs(
+            &client,
+            instance,
+            &desired.image,
+            &desired.specs,
+            &desired.ports,
+            Some(&desired.options),
+        )
+        .await
+        .unwrap();
+        let current = get_instances_with_config(&client).await.unwrap();
+        assert!(plan(&manifest[..1], &current)[0].changes.is_empty());
+    }
+}
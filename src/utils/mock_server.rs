@@ -0,0 +1,713 @@
+// minimal in-process stand-in for fly's machines and graphql apis, used by the test suite
+use super::client::FlyClient;
+use super::connector::{Connector, Transfer};
+use super::error::SpsdError;
+use super::machines;
+use super::types::{Instance, InstanceSpecs, MachineOptions, PortForward, PortSpec};
+use crate::cli::progress::Progress;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+pub const MOCK_APP_NAME: &str = "spsd-test-app";
+pub const MOCK_API_KEY: &str = "spsd-test-key";
+
+#[derive(Default)]
+pub struct MockState {
+    pub machines: Vec<Value>,
+    pub volumes: Vec<Value>,
+    pub snapshots: Vec<Value>,
+    // secret name to value, which the real api never returns
+    pub secrets: BTreeMap<String, String>,
+    pub ip_addresses: Vec<Value>,
+    pub shared_ip_address: Option<String>,
+    // apps created through the api, next to MOCK_APP_NAME which always exists
+    pub apps: Vec<Value>,
+    // ENTRYPOINTs of images in the mock registry, by repository. other images have none
+    pub image_entrypoints: HashMap<String, Vec<String>>,
+    // repositories the mock registry only serves with credentials, which spsd has none for
+    pub private_images: HashSet<String>,
+    failures: Vec<(String, String, u16)>,
+    next_id: u64,
+}
+
+impl MockState {
+    fn generate_id(&mut self, prefix: &str) -> String {
+        self.next_id += 1;
+        format!("{}{:012x}", prefix, self.next_id)
+    }
+}
+
+pub struct MockServer {
+    pub url: String,
+    pub graphql_url: String,
+    pub state: Arc<Mutex<MockState>>,
+}
+
+struct MockRequest {
+    method: String,
+    path: String,
+    headers: HashMap<String, String>,
+    body: String,
+}
+
+impl MockServer {
+    pub fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind mock server");
+        let address = listener.local_addr().unwrap();
+        let url = format!("http://{}/v1", address);
+        let graphql_url = format!("http://{}/graphql", address);
+        let state = Arc::new(Mutex::new(MockState::default()));
+
+        let server_state = Arc::clone(&state);
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let state = Arc::clone(&server_state);
+                thread::spawn(move || handle_connection(stream, state));
+            }
+        });
+
+        MockServer {
+            url,
+            graphql_url,
+            state,
+        }
+    }
+
+    pub fn machines(&self) -> Vec<Value> {
+        self.state.lock().unwrap().machines.clone()
+    }
+
+    pub fn volumes(&self) -> Vec<Value> {
+        self.state.lock().unwrap().volumes.clone()
+    }
+
+    pub fn secrets(&self) -> BTreeMap<String, String> {
+        self.state.lock().unwrap().secrets.clone()
+    }
+
+    // makes the next request with this method whose path ends with `suffix` fail with `status`
+    pub fn fail_next(&self, method: &str, suffix: &str, status: u16) {
+        self.state
+            .lock()
+            .unwrap()
+            .failures
+            .push((method.to_string(), suffix.to_string(), status));
+    }
+
+    pub fn set_private_image(&self, repository: &str) {
+        self.state
+            .lock()
+            .unwrap()
+            .private_images
+            .insert(repository.to_string());
+    }
+
+    pub fn set_image_entrypoint(&self, repository: &str, entrypoint: &[&str]) {
+        self.state.lock().unwrap().image_entrypoints.insert(
+            repository.to_string(),
+            entrypoint.iter().map(|arg| arg.to_string()).collect(),
+        );
+    }
+
+    // a client for the mock app, authorized with the mock api key
+    pub fn client(&self) -> FlyClient {
+        self.client_for(MOCK_APP_NAME, MOCK_API_KEY)
+    }
+
+    // images are looked up in the mock registry too
+    pub fn client_for(&self, app_name: &str, api_key: &str) -> FlyClient {
+        FlyClient::new(&self.url, &self.graphql_url, app_name, api_key)
+            .unwrap()
+            .with_registry_url(self.url.trim_end_matches("/v1"))
+    }
+}
+
+// how create_test_instance provisions an instance, the smallest one `spsd new` allows by default
+pub struct TestInstance {
+    pub specs: InstanceSpecs,
+    pub ports: Vec<PortSpec>,
+    pub options: MachineOptions,
+    pub wait: bool,
+}
+
+impl Default for TestInstance {
+    fn default() -> Self {
+        TestInstance {
+            specs: InstanceSpecs {
+                cpu_kind: String::from("shared"),
+                cpu_count: 1,
+                memory_mb: 256,
+                volume_gb: 1,
+            },
+            ports: Vec::new(),
+            options: MachineOptions::default(),
+            wait: true,
+        }
+    }
+}
+
+pub async fn create_test_instance(
+    client: &FlyClient,
+    name: &str,
+    instance: TestInstance,
+) -> Result<Instance, SpsdError> {
+    machines::create_machine(
+        client,
+        name,
+        "ubuntu",
+        instance.specs,
+        "ams",
+        &instance.ports,
+        &instance.options,
+        &Progress::hidden(),
+        instance.wait,
+    )
+    .await
+}
+
+// runs commands locally instead of on the instance, and consoles exit straight away
+pub struct LocalConnector;
+
+impl Connector for LocalConnector {
+    fn console(&self, _instance: &Instance) -> tokio::process::Command {
+        tokio::process::Command::new("true")
+    }
+
+    fn exec(&self, _instance: &Instance, command: &[String]) -> tokio::process::Command {
+        let mut exec = tokio::process::Command::new("sh");
+        exec.arg("-c").arg(shell_words::join(command));
+        exec
+    }
+
+    fn copy(
+        &self,
+        _instance: &Instance,
+        _transfer: &Transfer,
+    ) -> Result<tokio::process::Command, SpsdError> {
+        Ok(tokio::process::Command::new("true"))
+    }
+
+    fn forward(&self, _instance: &Instance, _ports: PortForward) -> tokio::process::Command {
+        tokio::process::Command::new("true")
+    }
+}
+
+fn handle_connection(mut stream: TcpStream, state: Arc<Mutex<MockState>>) {
+    let request = match read_request(&stream) {
+        Some(request) => request,
+        None => return,
+    };
+    let (status, body) = route(&request, &mut state.lock().unwrap());
+    // null stands for an empty body, which some of fly's endpoints answer with
+    let body = match body {
+        Value::Null => String::new(),
+        body => body.to_string(),
+    };
+    let response = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        reason(status),
+        body.len(),
+        body
+    );
+    let _ = stream.write_all(response.as_bytes());
+}
+
+fn read_request(stream: &TcpStream) -> Option<MockRequest> {
+    let mut reader = BufReader::new(stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).ok()?;
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next()?.to_string();
+    let path = parts.next()?.to_string();
+
+    let mut headers = HashMap::new();
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).ok()?;
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((key, value)) = line.split_once(':') {
+            headers.insert(key.trim().to_lowercase(), value.trim().to_string());
+        }
+    }
+
+    let length: usize = headers
+        .get("content-length")
+        .and_then(|length| length.parse().ok())
+        .unwrap_or(0);
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).ok()?;
+
+    Some(MockRequest {
+        method,
+        path,
+        headers,
+        body: String::from_utf8_lossy(&body).to_string(),
+    })
+}
+
+fn route(request: &MockRequest, state: &mut MockState) -> (u16, Value) {
+    // registries don't use fly's api key, and must never be sent it
+    if let Some(path) = request.path.strip_prefix("/v2/") {
+        if request.headers.get("authorization") == Some(&format!("Bearer {}", MOCK_API_KEY)) {
+            return error(400, "fly api key sent to a registry");
+        }
+        return registry(path, state);
+    }
+
+    let authorized =
+        request.headers.get("authorization") == Some(&format!("Bearer {}", MOCK_API_KEY));
+    if !authorized {
+        return error(401, "unauthorized");
+    }
+
+    let path = request.path.split('?').next().unwrap_or_default();
+    let failure = state
+        .failures
+        .iter()
+        .position(|(method, suffix, _)| *method == request.method && path.ends_with(suffix));
+    if let Some(index) = failure {
+        let (_, _, status) = state.failures.remove(index);
+        return error(status, "injected failure");
+    }
+
+    if path == "/graphql" {
+        return graphql(request, state);
+    }
+
+    match (request.method.as_str(), path) {
+        ("POST", "/v1/apps") => return create_app(request, state),
+        ("GET", app) if app.starts_with("/v1/apps/") && app.matches('/').count() == 3 => {
+            return get_app(state, &app["/v1/apps/".len()..])
+        }
+        _ => {}
+    }
+
+    let prefix = format!("/v1/apps/{}/", MOCK_APP_NAME);
+    let segments: Vec<&str> = match path.strip_prefix(&prefix) {
+        Some(rest) => rest.split('/').collect(),
+        None => return error(404, "app not found"),
+    };
+
+    match (request.method.as_str(), segments.as_slice()) {
+        ("GET", ["machines"]) => (200, Value::Array(state.machines.clone())),
+        ("POST", ["machines"]) => create_machine(request, state),
+        ("GET", ["machines", id]) => match find(&state.machines, id) {
+            Some(index) => (200, state.machines[index].clone()),
+            None => error(404, "machine not found"),
+        },
+        ("POST", ["machines", id]) => update_machine(request, state, id),
+        // like on fly, the machine is only gone (and its volume free) once it's done destroying
+        ("DELETE", ["machines", id]) => set_machine_state(state, id, "destroying"),
+        ("POST", ["machines", id, "start"]) => set_machine_state(state, id, "started"),
+        ("POST", ["machines", id, "stop"]) => set_machine_state(state, id, "stopped"),
+        ("GET", ["machines", id, "wait"]) => wait_machine(request, state, id),
+        ("GET", ["volumes"]) => (200, Value::Array(state.volumes.clone())),
+        ("POST", ["volumes"]) => create_volume(request, state),
+        ("GET", ["volumes", id]) => match find(&state.volumes, id) {
+            Some(index) => (200, state.volumes[index].clone()),
+            None => error(404, "volume not found"),
+        },
+        ("GET", ["volumes", id, "snapshots"]) => {
+            let snapshots = state
+                .snapshots
+                .iter()
+                .filter(|snapshot| snapshot["volume_id"] == *id)
+                .cloned()
+                .collect();
+            (200, Value::Array(snapshots))
+        }
+        ("POST", ["volumes", id, "snapshots"]) => create_snapshot(state, id),
+        ("PUT", ["volumes", id, "extend"]) => extend_volume(request, state, id),
+        ("DELETE", ["volumes", id]) => match find(&state.volumes, id) {
+            Some(index) => {
+                let attached = state.volumes[index]["attached_machine_id"].as_str();
+                if attached.is_some_and(|machine_id| find(&state.machines, machine_id).is_some()) {
+                    return error(412, "volume is attached to a machine");
+                }
+                (200, state.volumes.remove(index))
+            }
+            None => error(404, "volume not found"),
+        },
+        _ => error(404, "not found"),
+    }
+}
+
+// answers the handful of operations spsd sends, telling them apart by their root field
+fn graphql(request: &MockRequest, state: &mut MockState) -> (u16, Value) {
+    let body: Value = match serde_json::from_str(&request.body) {
+        Ok(body) => body,
+        Err(_) => return error(400, "invalid graphql body"),
+    };
+    let query = body["query"].as_str().unwrap_or_default();
+    let variables = &body["variables"];
+    if query.contains("viewer") {
+        return (
+            200,
+            json!({ "data": { "viewer": { "email": "dev@example.com" } } }),
+        );
+    }
+    let app_name = variables["appName"]
+        .as_str()
+        .or(variables["input"]["appId"].as_str());
+    if app_name != Some(MOCK_APP_NAME) {
+        return graphql_error("Could not find App");
+    }
+
+    if query.contains("releaseIpAddress") {
+        let address = variables["input"]["ip"].as_str().unwrap_or_default();
+        state.ip_addresses.retain(|ip| ip["address"] != address);
+        if state.shared_ip_address.as_deref() == Some(address) {
+            state.shared_ip_address = None;
+        }
+        (
+            200,
+            json!({ "data": { "releaseIpAddress": { "app": { "name": MOCK_APP_NAME } } } }),
+        )
+    } else if query.contains("allocateIpAddress") {
+        let ip_type = variables["input"]["type"].as_str().unwrap_or_default();
+        state.next_id += 1;
+        let ip_address = match ip_type {
+            "shared_v4" => {
+                state.shared_ip_address = Some(format!("66.241.124.{}", state.next_id));
+                Value::Null
+            }
+            _ => {
+                let address = match ip_type {
+                    "v6" => format!("2a09:8280:1::{:x}", state.next_id),
+                    _ => format!("137.66.1.{}", state.next_id),
+                };
+                let ip_address = json!({
+                    "id": format!("ip_{}", state.next_id),
+                    "address": address,
+                    "type": ip_type,
+                    "region": "global",
+                    "createdAt": "2024-01-01T00:00:00Z"
+                });
+                state.ip_addresses.push(ip_address.clone());
+                ip_address
+            }
+        };
+        (
+            200,
+            json!({ "data": { "allocateIpAddress": { "ipAddress": ip_address } } }),
+        )
+    } else if query.contains("ipAddresses") {
+        (
+            200,
+            json!({ "data": { "app": {
+                "sharedIpAddress": state.shared_ip_address,
+                "ipAddresses": { "nodes": state.ip_addresses }
+            } } }),
+        )
+    } else if query.contains("unsetSecrets") {
+        for key in variables["input"]["keys"].as_array().into_iter().flatten() {
+            state.secrets.remove(key.as_str().unwrap_or_default());
+        }
+        (
+            200,
+            json!({ "data": { "unsetSecrets": { "app": { "name": MOCK_APP_NAME } } } }),
+        )
+    } else if query.contains("setSecrets") {
+        for secret in variables["input"]["secrets"]
+            .as_array()
+            .into_iter()
+            .flatten()
+        {
+            state.secrets.insert(
+                secret["key"].as_str().unwrap_or_default().to_string(),
+                secret["value"].as_str().unwrap_or_default().to_string(),
+            );
+        }
+        (
+            200,
+            json!({ "data": { "setSecrets": { "app": { "name": MOCK_APP_NAME } } } }),
+        )
+    } else if query.contains("secrets") {
+        let secrets: Vec<Value> = state
+            .secrets
+            .keys()
+            .map(|name| json!({ "name": name, "digest": "d1g3st", "createdAt": "2024-01-01T00:00:00Z" }))
+            .collect();
+        (200, json!({ "data": { "app": { "secrets": secrets } } }))
+    } else {
+        graphql_error("Unknown operation")
+    }
+}
+
+fn get_app(state: &MockState, name: &str) -> (u16, Value) {
+    if name == MOCK_APP_NAME {
+        return (
+            200,
+            json!({ "id": "app_0", "name": name, "organization": { "slug": "personal" } }),
+        );
+    }
+    match state.apps.iter().find(|app| app["name"] == name) {
+        Some(app) => (200, app.clone()),
+        None => error(404, "app not found"),
+    }
+}
+
+fn create_app(request: &MockRequest, state: &mut MockState) -> (u16, Value) {
+    let body: Value = match serde_json::from_str(&request.body) {
+        Ok(body) => body,
+        Err(_) => return error(400, "invalid app body"),
+    };
+    let name = body["app_name"].as_str().unwrap_or_default().to_string();
+    if get_app(state, &name).0 == 200 {
+        return error(422, "Name has already been taken");
+    }
+    let app = json!({
+        "id": state.generate_id("app_"),
+        "name": name,
+        "organization": { "slug": body["org_slug"] }
+    });
+    state.apps.push(app);
+    (201, Value::Null)
+}
+
+fn graphql_error(message: &str) -> (u16, Value) {
+    (
+        200,
+        json!({ "data": null, "errors": [{ "message": message }] }),
+    )
+}
+
+fn create_machine(request: &MockRequest, state: &mut MockState) -> (u16, Value) {
+    let body: Value = match serde_json::from_str(&request.body) {
+        Ok(body) => body,
+        Err(_) => return error(400, "invalid machine body"),
+    };
+    let volume_id = body["config"]["mounts"][0]["volume"].as_str();
+    if let Some(volume_id) = volume_id {
+        if find(&state.volumes, volume_id).is_none() {
+            return error(404, "volume not found");
+        }
+    }
+
+    let id = state.generate_id("");
+    let image = body["config"]["image"].as_str().unwrap_or_default();
+    let launched = body["skip_launch"] != json!(true);
+    let machine = json!({
+        "id": id,
+        "name": body["name"],
+        "state": if launched { "started" } else { "created" },
+        "region": body["region"],
+        "instance_id": state.generate_id("01H"),
+        "private_ip": "fdaa:0:1::2",
+        "config": body["config"],
+        "image_ref": {
+            "registry": "registry-1.docker.io",
+            "repository": image,
+            "tag": "latest",
+        },
+        "created_at": "2024-01-01T00:00:00Z",
+    });
+
+    if let Some(volume_id) = volume_id {
+        let index = find(&state.volumes, volume_id).unwrap();
+        state.volumes[index]["attached_machine_id"] = json!(id);
+    }
+    state.machines.push(machine.clone());
+    (200, machine)
+}
+
+fn update_machine(request: &MockRequest, state: &mut MockState, id: &str) -> (u16, Value) {
+    let body: Value = match serde_json::from_str(&request.body) {
+        Ok(body) => body,
+        Err(_) => return error(400, "invalid machine body"),
+    };
+    match find(&state.machines, id) {
+        Some(index) => {
+            // swapping the mounted volume moves the attachment like fly does
+            if let Some(volume_id) = body["config"]["mounts"][0]["volume"].as_str() {
+                for volume in state.volumes.iter_mut() {
+                    if volume["attached_machine_id"] == id {
+                        volume["attached_machine_id"] = Value::Null;
+                    }
+                    if volume["id"] == volume_id {
+                        volume["attached_machine_id"] = json!(id);
+                    }
+                }
+            }
+            let machine = &mut state.machines[index];
+            machine["config"] = body["config"].clone();
+            if body["skip_launch"] != json!(true) {
+                machine["state"] = json!("started");
+            }
+            (200, machine.clone())
+        }
+        None => error(404, "machine not found"),
+    }
+}
+
+// answers right away instead of blocking: 408 (fly's timeout response) unless the machine is already
+// in the requested state. destroying machines finish once they are waited for and are then gone
+// from the mock, like destroyed machines are from fly's list
+fn wait_machine(request: &MockRequest, state: &mut MockState, id: &str) -> (u16, Value) {
+    let wanted = match query_param(&request.path, "state") {
+        Some(wanted) => wanted,
+        None => return error(400, "state is required"),
+    };
+    match find(&state.machines, id) {
+        Some(index) if state.machines[index]["state"] == "destroying" && wanted == "destroyed" => {
+            state.machines.remove(index);
+            (200, json!({"ok": true}))
+        }
+        Some(index) if state.machines[index]["state"] == wanted => (200, json!({"ok": true})),
+        Some(_) => error(408, "deadline exceeded"),
+        None if wanted == "destroyed" => (200, json!({"ok": true})),
+        None => error(404, "machine not found"),
+    }
+}
+
+fn query_param<'a>(path: &'a str, name: &str) -> Option<&'a str> {
+    path.split_once('?')?
+        .1
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+}
+
+// serves a manifest whose config digest names the repository, so the config blob can be looked up
+fn registry(path: &str, state: &MockState) -> (u16, Value) {
+    let repository = path
+        .split_once("/manifests/")
+        .or_else(|| path.split_once("/blobs/"))
+        .map(|(repository, _)| repository);
+    if repository.is_some_and(|repository| state.private_images.contains(repository)) {
+        return error(401, "authentication required");
+    }
+    if path.is_empty() {
+        return (200, json!({}));
+    }
+    if let Some((repository, _)) = path.rsplit_once("/manifests/") {
+        return (
+            200,
+            json!({ "config": { "digest": format!("sha256:{}", repository.replace('/', "-")) } }),
+        );
+    }
+    match path.rsplit_once("/blobs/") {
+        Some((repository, _)) => (
+            200,
+            json!({ "config": { "Entrypoint": state.image_entrypoints.get(repository) } }),
+        ),
+        None => error(404, "not found"),
+    }
+}
+
+fn create_volume(request: &MockRequest, state: &mut MockState) -> (u16, Value) {
+    let body: Value = match serde_json::from_str(&request.body) {
+        Ok(body) => body,
+        Err(_) => return error(400, "invalid volume body"),
+    };
+    if let Some(source_volume_id) = body["source_volume_id"].as_str() {
+        if find(&state.volumes, source_volume_id).is_none() {
+            return error(404, "source volume not found");
+        }
+    }
+    let volume = json!({
+        "id": state.generate_id("vol_"),
+        "name": body["name"],
+        "state": "created",
+        "size_gb": body["size_gb"],
+        "region": body["region"],
+        "zone": "a1b2",
+        "encrypted": true,
+        "attached_machine_id": null,
+        "attached_alloc_id": null,
+        "created_at": "2024-01-01T00:00:00Z",
+        "blocks": 0,
+        "block_size": 0,
+        "blocks_free": 0,
+        "blocks_avail": 0,
+        "fstype": "ext4",
+        "snapshot_retention": 5,
+        "host_dedication_key": "",
+        "snapshot_id": body["snapshot_id"],
+        "source_volume_id": body["source_volume_id"],
+    });
+    state.volumes.push(volume.clone());
+    (200, volume)
+}
+
+fn create_snapshot(state: &mut MockState, volume_id: &str) -> (u16, Value) {
+    let size_gb = match find(&state.volumes, volume_id) {
+        Some(index) => state.volumes[index]["size_gb"].as_u64().unwrap_or_default(),
+        None => return error(404, "volume not found"),
+    };
+    let snapshot = json!({
+        "id": state.generate_id("vs_"),
+        "volume_id": volume_id,
+        "created_at": format!("2024-01-01T00:00:{:02}Z", state.snapshots.len()),
+        "digest": "",
+        "retention_days": 5,
+        "size": size_gb * 1024 * 1024 * 1024,
+        "status": "created",
+    });
+    state.snapshots.push(snapshot);
+    (200, Value::Null)
+}
+
+fn extend_volume(request: &MockRequest, state: &mut MockState, id: &str) -> (u16, Value) {
+    let body: Value = match serde_json::from_str(&request.body) {
+        Ok(body) => body,
+        Err(_) => return error(400, "invalid volume body"),
+    };
+    let index = match find(&state.volumes, id) {
+        Some(index) => index,
+        None => return error(404, "volume not found"),
+    };
+    if body["size_gb"].as_u64() <= state.volumes[index]["size_gb"].as_u64() {
+        return error(400, "volume can only be extended");
+    }
+    state.volumes[index]["size_gb"] = body["size_gb"].clone();
+    let needs_restart = state.volumes[index]["attached_machine_id"]
+        .as_str()
+        .and_then(|machine_id| find(&state.machines, machine_id))
+        .is_some_and(|machine| state.machines[machine]["state"] == "started");
+    (
+        200,
+        json!({ "volume": state.volumes[index], "needs_restart": needs_restart }),
+    )
+}
+
+fn set_machine_state(state: &mut MockState, id: &str, machine_state: &str) -> (u16, Value) {
+    match find(&state.machines, id) {
+        Some(index) => {
+            state.machines[index]["state"] = json!(machine_state);
+            (200, json!({"ok": true}))
+        }
+        None => error(404, "machine not found"),
+    }
+}
+
+fn find(items: &[Value], id: &str) -> Option<usize> {
+    items.iter().position(|item| item["id"] == id)
+}
+
+fn error(status: u16, message: &str) -> (u16, Value) {
+    (status, json!({ "error": message }))
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        201 => "Created",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        404 => "Not Found",
+        408 => "Request Timeout",
+        412 => "Precondition Failed",
+        422 => "Unprocessable Entity",
+        _ => "Internal Server Error",
+    }
+}
@@ -1,4 +1,17 @@
+pub mod client;
 pub mod config;
-mod request_utils;
+pub mod connector;
+pub mod credentials;
+pub mod env;
+pub mod error;
+pub mod idle;
 pub mod machines;
+pub mod manifest;
+#[cfg(test)]
+pub mod mock_server;
+pub mod registry;
+mod request_utils;
+pub mod rollback;
+pub mod snapshots;
 pub mod types;
+pub mod usage;
@@ -0,0 +1,251 @@
+use super::client::FlyClient;
+use super::error::SpsdError;
+use reqwest::header::{HeaderMap, ACCEPT, WWW_AUTHENTICATE};
+use reqwest::{RequestBuilder, StatusCode};
+use serde_json::Value;
+use std::time::Duration;
+
+const DOCKER_HUB: &str = "registry-1.docker.io";
+// the only registry fly's api key is sent to
+const FLY_REGISTRY: &str = "registry.fly.io";
+const MANIFEST_TYPES: &str = "application/vnd.oci.image.index.v1+json, \
+    application/vnd.docker.distribution.manifest.list.v2+json, \
+    application/vnd.oci.image.manifest.v1+json, \
+    application/vnd.docker.distribution.manifest.v2+json";
+// registries answer quickly or not at all, and a start shouldn't hang on one
+const REGISTRY_TIMEOUT: Duration = Duration::from_secs(30);
+
+// an image name split the way the registry api wants it, e.g. "ubuntu" is
+// registry-1.docker.io/library/ubuntu:latest
+#[derive(Debug, PartialEq)]
+struct ImageName {
+    registry: String,
+    repository: String,
+    reference: String,
+}
+
+fn split_image_reference(image: &str) -> ImageName {
+    let (name, digest) = match image.split_once('@') {
+        Some((name, digest)) => (name, Some(digest)),
+        None => (image, None),
+    };
+    let (name, tag) = match name.rsplit_once(':') {
+        Some((name, tag)) if !tag.contains('/') => (name, tag),
+        _ => (name, "latest"),
+    };
+    let (registry, repository) = match name.split_once('/') {
+        Some((host, path)) if host.contains('.') || host.contains(':') || host == "localhost" => {
+            (host, path.to_string())
+        }
+        Some(_) => (DOCKER_HUB, name.to_string()),
+        None => (DOCKER_HUB, format!("library/{}", name)),
+    };
+    ImageName {
+        registry: match registry {
+            "docker.io" | "index.docker.io" => DOCKER_HUB.to_string(),
+            registry => registry.to_string(),
+        },
+        repository,
+        reference: digest.unwrap_or(tag).to_string(),
+    }
+}
+
+// a session with one registry, holding the token it handed out for pulling `name`
+struct Registry<'a> {
+    client: &'a FlyClient,
+    base_url: String,
+    name: &'a ImageName,
+    token: Option<String>,
+}
+
+impl Registry<'_> {
+    // fly's api key is only sent to fly's own registry
+    fn api_key(&self) -> Option<&str> {
+        (self.name.registry == FLY_REGISTRY).then(|| self.client.api_key())
+    }
+
+    fn request(&self, url: &str, accept: &str) -> RequestBuilder {
+        let request = self
+            .client
+            .http()
+            .get(url)
+            .header(ACCEPT, accept)
+            .timeout(REGISTRY_TIMEOUT);
+        match (&self.token, self.api_key()) {
+            (Some(token), _) => request.bearer_auth(token),
+            (None, Some(api_key)) => request.basic_auth("x", Some(api_key)),
+            (None, None) => request,
+        }
+    }
+
+    // registries answer `/v2/` with the challenge to get a token from, if they need one
+    async fn authenticate(&mut self) -> Result<(), SpsdError> {
+        let url = format!("{}/v2/", self.base_url);
+        let response = self.request(&url, "application/json").send().await?;
+        if response.status() == StatusCode::UNAUTHORIZED {
+            self.token = self.fetch_token(response.headers()).await?;
+        }
+        Ok(())
+    }
+
+    // follows a `Bearer realm=...,service=...` challenge to get a pull token, which registries like
+    // docker hub hand out anonymously for public images
+    async fn fetch_token(&self, headers: &HeaderMap) -> Result<Option<String>, SpsdError> {
+        let challenge = headers
+            .get(WWW_AUTHENTICATE)
+            .and_then(|challenge| challenge.to_str().ok())
+            .and_then(|challenge| challenge.strip_prefix("Bearer "))
+            .unwrap_or_default();
+        let mut realm = None;
+        let mut query = vec![(
+            String::from("scope"),
+            format!("repository:{}:pull", self.name.repository),
+        )];
+        for param in challenge.split(',') {
+            if let Some((key, value)) = param.trim().split_once('=') {
+                let value = value.trim_matches('"').to_string();
+                match key {
+                    "realm" => realm = Some(value),
+                    "service" => query.push((key.to_string(), value)),
+                    _ => {}
+                }
+            }
+        }
+        let realm = match realm {
+            Some(realm) => realm,
+            None => return Ok(None),
+        };
+
+        let mut request = self
+            .client
+            .http()
+            .get(realm)
+            .query(&query)
+            .timeout(REGISTRY_TIMEOUT);
+        if let Some(api_key) = self.api_key() {
+            request = request.basic_auth("x", Some(api_key));
+        }
+        let response: Value = self.client.send(request, None).await?;
+        Ok(response["token"]
+            .as_str()
+            .or(response["access_token"].as_str())
+            .map(String::from))
+    }
+
+    async fn get(&self, path: &str, accept: &str) -> Result<Value, SpsdError> {
+        let url = format!("{}/v2/{}/{}", self.base_url, self.name.repository, path);
+        match self.client.send(self.request(&url, accept), None).await {
+            Err(SpsdError::Auth(_)) => Err(SpsdError::Auth(format!(
+                "{}/{} can't be pulled without credentials, and spsd only has them for {}",
+                self.name.registry, self.name.repository, FLY_REGISTRY
+            ))),
+            result => result,
+        }
+    }
+}
+
+// the ENTRYPOINT `image` was built with (empty if it has none), read from its config in the
+// registry. the client's registry url, if set, stands in for the registry the image names
+pub async fn image_entrypoint(client: &FlyClient, image: &str) -> Result<Vec<String>, SpsdError> {
+    let name = split_image_reference(image);
+    let mut registry = Registry {
+        client,
+        base_url: client
+            .registry_url()
+            .map(String::from)
+            .unwrap_or_else(|| format!("https://{}", name.registry)),
+        name: &name,
+        token: None,
+    };
+    registry.authenticate().await?;
+
+    let mut manifest = registry
+        .get(&format!("manifests/{}", name.reference), MANIFEST_TYPES)
+        .await?;
+    // multi-platform images list a manifest per platform, and fly machines run linux/amd64
+    if let Some(platforms) = manifest["manifests"].as_array() {
+        let digest = platforms
+            .iter()
+            .find(|platform| {
+                platform["platform"]["os"] == "linux"
+                    && platform["platform"]["architecture"] == "amd64"
+            })
+            .and_then(|platform| platform["digest"].as_str())
+            .ok_or_else(|| {
+                SpsdError::NotFound(format!("Image {} has no linux/amd64 variant", image))
+            })?
+            .to_string();
+        manifest = registry
+            .get(&format!("manifests/{}", digest), MANIFEST_TYPES)
+            .await?;
+    }
+
+    let config_digest = manifest["config"]["digest"].as_str().ok_or_else(|| {
+        SpsdError::CommandFailed(format!("Manifest of image {} has no config", image))
+    })?;
+    let config = registry
+        .get(&format!("blobs/{}", config_digest), "application/json")
+        .await?;
+    Ok(config["config"]["Entrypoint"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(Value::as_str)
+        .map(String::from)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::mock_server::MockServer;
+
+    #[test]
+    fn image_names_are_split_into_registry_repository_and_reference() {
+        let name = |registry: &str, repository: &str, reference: &str| ImageName {
+            registry: registry.to_string(),
+            repository: repository.to_string(),
+            reference: reference.to_string(),
+        };
+        assert_eq!(
+            split_image_reference("ubuntu"),
+            name(DOCKER_HUB, "library/ubuntu", "latest")
+        );
+        assert_eq!(
+            split_image_reference("jupyter/base-notebook:2024-01-01"),
+            name(DOCKER_HUB, "jupyter/base-notebook", "2024-01-01")
+        );
+        assert_eq!(
+            split_image_reference("registry.fly.io/devbox@sha256:abc"),
+            name(FLY_REGISTRY, "devbox", "sha256:abc")
+        );
+        assert_eq!(
+            split_image_reference("localhost:5000/tools/dev:1.2"),
+            name("localhost:5000", "tools/dev", "1.2")
+        );
+    }
+
+    #[tokio::test]
+    async fn private_registries_fail_with_a_clear_error() {
+        let server = MockServer::start();
+        server.set_private_image("team/devbox");
+        let client = server.client();
+
+        let error = image_entrypoint(&client, "registry.example.com/team/devbox:1.0")
+            .await
+            .unwrap_err();
+        assert!(matches!(error, SpsdError::Auth(_)));
+        assert!(error
+            .to_string()
+            .contains("registry.example.com/team/devbox can't be pulled without credentials"));
+
+        // public images are looked up without fly's api key, which the mock registry refuses
+        server.set_image_entrypoint("team/tools", &["/init"]);
+        assert_eq!(
+            image_entrypoint(&client, "registry.example.com/team/tools")
+                .await
+                .unwrap(),
+            vec![String::from("/init")]
+        );
+    }
+}
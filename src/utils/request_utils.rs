@@ -1,10 +1,12 @@
-use super::config::{get_api_key, get_app_name};
-use super::types::{parse_state, Instance, InstanceSpecs};
-use crate::utils::types::Machines;
-use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION, CONTENT_TYPE};
-use std::error::Error;
+use super::error::SpsdError;
+use super::idle::IDLE_TIMEOUT_KEY;
+use super::types::{
+    parse_state, InitCommand, Instance, InstanceSpecs, Machine, MachineOptions, PortSpec, Protocol,
+};
+use serde_json::Value;
+use std::collections::BTreeMap;
 
-pub fn parse_response_body(machines: Machines) -> Result<Vec<Instance>, Box<dyn Error>> {
+pub fn parse_response_body(machines: &[Machine]) -> Result<Vec<Instance>, SpsdError> {
     let mut instances = Vec::new();
     for machine in machines.iter() {
         instances.push(Instance {
@@ -12,29 +14,50 @@ pub fn parse_response_body(machines: Machines) -> Result<Vec<Instance>, Box<dyn
             volume_id: machine
                 .config
                 .mounts
-                .get(0)
+                .first()
                 .map(|mount| mount.volume.clone())
                 .unwrap_or_default(),
             name: machine.name.clone(),
             image: machine.config.image.clone(),
             specs: match &machine.config.guest {
                 Some(guest) => InstanceSpecs {
+                    cpu_kind: guest.cpu_kind.clone(),
                     cpu_count: guest.cpus,
                     memory_mb: guest.memory_mb,
                     volume_gb: machine
                         .config
                         .mounts
-                        .get(0)
+                        .first()
                         .map(|mount| mount.size_gb)
                         .unwrap_or_default(),
                 },
                 None => InstanceSpecs::phony(),
             },
             region: machine.region.clone(),
-            port: match &machine.config.services {
-                Some(services) => Some(services.get(0).unwrap().internal_port),
-                None => None,
-            },
+            ports: machine
+                .config
+                .services
+                .iter()
+                .flatten()
+                .flat_map(|service| {
+                    service.ports.iter().map(|port| PortSpec {
+                        public_port: port.port,
+                        internal_port: service.internal_port,
+                        protocol: match service.protocol.as_str() {
+                            "udp" => Protocol::Udp,
+                            _ => Protocol::Tcp,
+                        },
+                        handlers: port.handlers.clone().unwrap_or_default(),
+                    })
+                })
+                .collect(),
+            private_ip: machine.private_ip.clone(),
+            idle_timeout: machine
+                .config
+                .metadata
+                .as_ref()
+                .and_then(|metadata| metadata.get(IDLE_TIMEOUT_KEY))
+                .and_then(|timeout| timeout.parse().ok()),
             state: parse_state(&machine.state),
         })
     }
@@ -47,24 +70,14 @@ pub fn create_body_from_specs(
     specs: InstanceSpecs,
     region: &str,
     volume_id: &str,
-    port: Option<u16>,
-) -> Result<String, Box<dyn std::error::Error>> {
+    ports: &[PortSpec],
+    options: &MachineOptions,
+) -> Value {
     let mut body = serde_json::json!({
         "name": name,
         "region": region,
         "config": {
-            "init": {
-                "exec": [
-                    "/bin/sleep",
-                    "inf"
-                ]
-            },
             "image": image,
-            "guest": {
-                "cpu_kind": "shared",
-                "cpus": specs.cpu_count,
-                "memory_mb": specs.memory_mb
-            },
             "mounts": [{
                 "encrypted": true,
                 "name": name,
@@ -77,37 +90,86 @@ pub fn create_body_from_specs(
         }
     });
 
-    if let Some(port) = port {
-        body["config"]["services"] = serde_json::json!([{
-            "ports": [
-                {
-                    "port": port,
-                    "handlers": [
-                    "http"
-                    ]
-                }
-            ],
-            "protocol": "tcp",
-            "internal_port": port
-        }]);
+    let config = &mut body["config"];
+    set_guest(config, &specs);
+    set_services(config, ports);
+    set_env(config, &options.env);
+    set_files(config, &options.files);
+    set_init(config, &options.init);
+    body
+}
+
+pub fn set_guest(config: &mut Value, specs: &InstanceSpecs) {
+    config["guest"] = serde_json::json!({
+        "cpu_kind": specs.cpu_kind,
+        "cpus": specs.cpu_count,
+        "memory_mb": specs.memory_mb
+    });
+}
+
+// one service per exposed port so each can have its own protocol and handlers
+pub fn set_services(config: &mut Value, ports: &[PortSpec]) {
+    if ports.is_empty() {
+        remove_key(config, "services");
+        return;
     }
+    let services: Vec<Value> = ports
+        .iter()
+        .map(|port| {
+            serde_json::json!({
+                "ports": [
+                    {
+                        "port": port.public_port,
+                        "handlers": port.handlers
+                    }
+                ],
+                "protocol": port.protocol,
+                "internal_port": port.internal_port
+            })
+        })
+        .collect();
+    config["services"] = Value::Array(services);
+}
 
-    Ok(serde_json::to_string(&body)?)
+pub fn set_env(config: &mut Value, env: &BTreeMap<String, String>) {
+    if env.is_empty() {
+        remove_key(config, "env");
+    } else {
+        config["env"] = serde_json::json!(env);
+    }
 }
 
-pub fn get_headers() -> Result<HeaderMap, Box<dyn Error>> {
-    let mut headers = HeaderMap::new();
-    headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+pub fn set_files(config: &mut Value, files: &BTreeMap<String, String>) {
+    if files.is_empty() {
+        remove_key(config, "files");
+    } else {
+        config["files"] = files
+            .iter()
+            .map(|(guest_path, raw_value)| {
+                serde_json::json!({ "guest_path": guest_path, "raw_value": raw_value })
+            })
+            .collect();
+    }
+}
 
-    let authorization_value = HeaderValue::from_str(&format!("Bearer {}", get_api_key()?))?;
-    headers.insert(AUTHORIZATION, authorization_value);
-    Ok(headers)
+pub fn set_init(config: &mut Value, init: &InitCommand) {
+    config["init"] = match init {
+        InitCommand::Sleep => serde_json::json!({ "exec": ["/bin/sleep", "inf"] }),
+        InitCommand::Image { entrypoint, cmd } => {
+            let mut init = serde_json::json!({});
+            if let Some(entrypoint) = entrypoint {
+                init["entrypoint"] = serde_json::json!(entrypoint);
+            }
+            if let Some(cmd) = cmd {
+                init["cmd"] = serde_json::json!(cmd);
+            }
+            init
+        }
+    };
 }
 
-pub fn get_hostname() -> Result<String, Box<dyn Error>> {
-    let app_name = get_app_name();
-    match app_name {
-        Ok(app_name) => Ok(format!("https://api.machines.dev/v1/apps/{}", app_name)),
-        Err(error) => Err(error),
+fn remove_key(config: &mut Value, key: &str) {
+    if let Some(config) = config.as_object_mut() {
+        config.remove(key);
     }
 }
@@ -0,0 +1,63 @@
+use super::client::FlyClient;
+use super::error::SpsdError;
+use super::machines;
+use std::fmt;
+
+pub enum Resource {
+    Volume(String),
+    Machine(String),
+}
+
+impl fmt::Display for Resource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Resource::Volume(id) => write!(f, "volume {}", id),
+            Resource::Machine(id) => write!(f, "machine {}", id),
+        }
+    }
+}
+
+// tracks resources created while provisioning an instance so they can be torn down on failure
+pub struct Rollback<'a> {
+    client: &'a FlyClient,
+    resources: Vec<Resource>,
+}
+
+impl<'a> Rollback<'a> {
+    pub fn new(client: &'a FlyClient) -> Self {
+        Rollback {
+            client,
+            resources: Vec::new(),
+        }
+    }
+
+    pub fn track(&mut self, resource: Resource) {
+        self.resources.push(resource);
+    }
+
+    // deletes tracked resources newest first and wraps the original error with what happened
+    pub async fn undo(self, error: SpsdError) -> SpsdError {
+        if self.resources.is_empty() {
+            return error;
+        }
+
+        let mut cleaned_up = Vec::new();
+        let mut leaked = Vec::new();
+        for resource in self.resources.into_iter().rev() {
+            let result = match &resource {
+                Resource::Machine(id) => machines::destroy_machine(self.client, id, true).await,
+                Resource::Volume(id) => self.client.delete_volume(id).await.map(|_| ()),
+            };
+            match result {
+                Ok(_) => cleaned_up.push(resource.to_string()),
+                Err(_) => leaked.push(resource.to_string()),
+            }
+        }
+
+        SpsdError::Rollback {
+            source: Box::new(error),
+            cleaned_up,
+            leaked,
+        }
+    }
+}
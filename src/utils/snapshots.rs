@@ -0,0 +1,113 @@
+use super::client::FlyClient;
+use super::error::SpsdError;
+use super::machines::update_machine_config;
+use super::rollback::{Resource, Rollback};
+use super::types::{Instance, InstanceState, Snapshot};
+use serde_json::Value;
+
+pub async fn create_snapshot(client: &FlyClient, instance: &Instance) -> Result<(), SpsdError> {
+    client.create_snapshot(&instance.volume_id).await?;
+    Ok(())
+}
+
+// snapshots of the instance's current volume, oldest first
+pub async fn list_snapshots(
+    client: &FlyClient,
+    instance: &Instance,
+) -> Result<Vec<Snapshot>, SpsdError> {
+    let mut snapshots = client.list_snapshots(&instance.volume_id).await?;
+    snapshots.sort_by(|a, b| a.created_at.cmp(&b.created_at));
+    Ok(snapshots)
+}
+
+// creates a volume from `snapshot_id` (the latest snapshot by default) and mounts it in place of
+// the current one, returning the id of the volume that was replaced
+pub async fn restore_snapshot(
+    client: &FlyClient,
+    instance: &Instance,
+    snapshot_id: Option<&str>,
+) -> Result<String, SpsdError> {
+    if instance.state != InstanceState::Stopped {
+        return Err(SpsdError::Validation(format!(
+            "Stop instance {} before restoring a snapshot",
+            instance.name
+        )));
+    }
+
+    let snapshots = list_snapshots(client, instance).await?;
+    let snapshot = match snapshot_id {
+        Some(id) => snapshots.iter().find(|snapshot| snapshot.id == id),
+        None => snapshots.last(),
+    }
+    .ok_or_else(|| {
+        SpsdError::NotFound(match snapshot_id {
+            Some(id) => format!("Snapshot {} not found for instance {}", id, instance.name),
+            None => format!("Instance {} has no snapshots", instance.name),
+        })
+    })?;
+
+    let mut rollback = Rollback::new(client);
+    let result = swap_volume(client, &mut rollback, instance, &snapshot.id).await;
+    match result {
+        Ok(_) => Ok(instance.volume_id.clone()),
+        Err(error) => Err(rollback.undo(error).await),
+    }
+}
+
+async fn swap_volume(
+    client: &FlyClient,
+    rollback: &mut Rollback<'_>,
+    instance: &Instance,
+    snapshot_id: &str,
+) -> Result<(), SpsdError> {
+    let volume = client
+        .create_volume_from_snapshot(
+            &instance.name,
+            instance.specs.volume_gb,
+            &instance.region,
+            snapshot_id,
+        )
+        .await?;
+    rollback.track(Resource::Volume(volume.id.clone()));
+
+    update_machine_config(client, instance, |config| {
+        config["mounts"][0]["volume"] = Value::from(volume.id.as_str());
+        config["mounts"][0]["size_gb"] = Value::from(volume.size_gb);
+    })
+    .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::machines::get_instance;
+    use crate::utils::mock_server::{create_test_instance, MockServer, TestInstance};
+
+    #[tokio::test]
+    async fn restore_mounts_a_volume_created_from_the_latest_snapshot() {
+        let server = MockServer::start();
+        let client = server.client();
+        let instance = create_test_instance(&client, "devbox", TestInstance::default())
+            .await
+            .unwrap();
+
+        create_snapshot(&client, &instance).await.unwrap();
+        create_snapshot(&client, &instance).await.unwrap();
+        let snapshots = list_snapshots(&client, &instance).await.unwrap();
+        assert_eq!(snapshots.len(), 2);
+
+        let old_volume_id = restore_snapshot(&client, &instance, None).await.unwrap();
+        assert_eq!(old_volume_id, instance.volume_id);
+
+        let restored = get_instance(&client, "devbox").await.unwrap();
+        let volume = server
+            .volumes()
+            .into_iter()
+            .find(|volume| volume["id"] == restored.volume_id)
+            .unwrap();
+        assert_ne!(restored.volume_id, old_volume_id);
+        assert_eq!(volume["snapshot_id"], snapshots[1].id);
+        assert_eq!(volume["attached_machine_id"], restored.machine_id);
+    }
+}
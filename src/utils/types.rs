@@ -1,9 +1,12 @@
+use super::idle;
 use clap::Parser;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::str::FromStr;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct Instance {
     pub machine_id: String,
     pub volume_id: String,
@@ -11,7 +14,9 @@ pub struct Instance {
     pub image: String,
     pub specs: InstanceSpecs,
     pub region: String,
-    pub port: Option<u16>,
+    pub ports: Vec<PortSpec>,
+    pub private_ip: String,
+    pub idle_timeout: Option<u64>,
     pub state: InstanceState,
 }
 
@@ -37,11 +42,12 @@ pub struct InstanceInput {
     pub region: Option<String>,
 
     #[clap(short, long)]
-    pub port: Option<u16>,
+    pub ports: Vec<PortSpec>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct InstanceSpecs {
+    pub cpu_kind: String,
     pub cpu_count: u32,
     pub memory_mb: u32,
     pub volume_gb: u32,
@@ -50,6 +56,7 @@ pub struct InstanceSpecs {
 impl InstanceSpecs {
     pub fn phony() -> Self {
         Self {
+            cpu_kind: String::new(),
             cpu_count: 0,
             memory_mb: 0,
             volume_gb: 0,
@@ -57,34 +64,424 @@ impl InstanceSpecs {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
+pub struct MachineOptions {
+    pub env: BTreeMap<String, String>,
+    // guest path to base64 encoded contents
+    pub files: BTreeMap<String, String>,
+    pub init: InitCommand,
+}
+
+// what a machine runs once it boots
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum InitCommand {
+    // sleep forever so the machine stays up for connections, ignoring the image's command
+    #[default]
+    Sleep,
+    // the image's own entrypoint and cmd, either of which can be overridden
+    Image {
+        entrypoint: Option<Vec<String>>,
+        cmd: Option<Vec<String>>,
+    },
+}
+
+impl InitCommand {
+    pub fn new(
+        entrypoint: Option<Vec<String>>,
+        cmd: Option<Vec<String>>,
+        keep_image_cmd: bool,
+    ) -> Self {
+        if entrypoint.is_none() && cmd.is_none() && !keep_image_cmd {
+            InitCommand::Sleep
+        } else {
+            InitCommand::Image { entrypoint, cmd }
+        }
+    }
+}
+
+impl fmt::Display for InitCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InitCommand::Sleep => write!(f, "sleep"),
+            InitCommand::Image {
+                entrypoint: None,
+                cmd: None,
+            } => write!(f, "image default"),
+            InitCommand::Image { entrypoint, cmd } => {
+                let parts: Vec<String> = [("entrypoint", entrypoint), ("cmd", cmd)]
+                    .into_iter()
+                    .filter_map(|(label, words)| {
+                        words
+                            .as_ref()
+                            .map(|words| format!("{} {}", label, shell_words::join(words)))
+                    })
+                    .collect();
+                write!(f, "{}", parts.join(", "))
+            }
+        }
+    }
+}
+
+impl MachineOptions {
+    // the env and files an existing machine was configured with
+    pub fn from_config(config: &MachineConfig) -> Self {
+        MachineOptions {
+            env: config.env.clone().unwrap_or_default().into_iter().collect(),
+            files: config
+                .files
+                .iter()
+                .flatten()
+                .map(|file| {
+                    (
+                        file.guest_path.clone(),
+                        file.raw_value.clone().unwrap_or_default(),
+                    )
+                })
+                .collect(),
+            init: match (&config.init.exec, &config.init.entrypoint) {
+                (Some(_), _) => InitCommand::Sleep,
+                (None, entrypoint) => InitCommand::Image {
+                    entrypoint: entrypoint
+                        .as_deref()
+                        .map(idle::without_watchdog)
+                        .filter(|entrypoint| !entrypoint.is_empty())
+                        .map(<[String]>::to_vec),
+                    cmd: config.init.cmd.clone(),
+                },
+            },
+        }
+    }
+}
+
+// the lifecycle states of a fly machine, named like fly names them
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
 pub enum InstanceState {
-    Running,
+    Created,
+    Starting,
+    Started,
+    Stopping,
+    Stopped,
+    Suspending,
+    Suspended,
+    Replacing,
+    Destroying,
+    Destroyed,
+    Failed,
+    Unknown,
+}
+
+impl InstanceState {
+    pub fn is_running(&self) -> bool {
+        matches!(self, InstanceState::Starting | InstanceState::Started)
+    }
+
+    // what asking for `transition` does to a machine in this state. machines that are changing
+    // state, being replaced or destroyed can't be touched until that's done
+    pub fn check(&self, transition: Transition) -> TransitionCheck {
+        use InstanceState::*;
+        use TransitionCheck::*;
+        match (transition, self) {
+            (Transition::Start, Created | Stopped | Suspended | Unknown) => Allowed,
+            (Transition::Start, Starting | Started) => Redundant,
+            (Transition::Stop, Starting | Started | Stopping | Unknown) => Allowed,
+            (Transition::Stop, Created | Stopped | Suspended | Failed) => Redundant,
+            (Transition::Update, Created | Started | Stopped | Suspended | Failed | Unknown) => {
+                Allowed
+            }
+            (Transition::Destroy, Replacing | Destroying | Destroyed) => Refused,
+            (Transition::Destroy, _) => Allowed,
+            _ => Refused,
+        }
+    }
+}
+
+impl fmt::Display for InstanceState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = match self {
+            InstanceState::Created => "created",
+            InstanceState::Starting => "starting",
+            InstanceState::Started => "started",
+            InstanceState::Stopping => "stopping",
+            InstanceState::Stopped => "stopped",
+            InstanceState::Suspending => "suspending",
+            InstanceState::Suspended => "suspended",
+            InstanceState::Replacing => "replacing",
+            InstanceState::Destroying => "destroying",
+            InstanceState::Destroyed => "destroyed",
+            InstanceState::Failed => "failed",
+            InstanceState::Unknown => "unknown",
+        };
+        write!(f, "{}", state)
+    }
+}
+
+// the changes spsd makes to a machine's state
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Transition {
+    Start,
+    Stop,
+    Update,
+    Destroy,
+}
+
+impl fmt::Display for Transition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Transition::Start => write!(f, "start"),
+            Transition::Stop => write!(f, "stop"),
+            Transition::Update => write!(f, "update"),
+            Transition::Destroy => write!(f, "destroy"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TransitionCheck {
+    Allowed,
+    // the machine is already where the transition would take it
+    Redundant,
+    Refused,
+}
+
+// the states fly's wait endpoint can wait for
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WaitState {
+    Started,
     Stopped,
+    Suspended,
+    Destroyed,
+}
+
+impl fmt::Display for WaitState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WaitState::Started => write!(f, "started"),
+            WaitState::Stopped => write!(f, "stopped"),
+            WaitState::Suspended => write!(f, "suspended"),
+            WaitState::Destroyed => write!(f, "destroyed"),
+        }
+    }
+}
+
+impl FromStr for WaitState {
+    type Err = String;
+
+    fn from_str(state: &str) -> Result<Self, Self::Err> {
+        match state {
+            "started" => Ok(WaitState::Started),
+            "stopped" => Ok(WaitState::Stopped),
+            "suspended" => Ok(WaitState::Suspended),
+            "destroyed" => Ok(WaitState::Destroyed),
+            _ => Err(String::from(
+                "State must be one of started, stopped, suspended or destroyed",
+            )),
+        }
+    }
 }
 
 pub fn parse_state(state: &str) -> InstanceState {
     match state {
-        "starting" | "started" => InstanceState::Running,
-        _ => InstanceState::Stopped,
+        "created" => InstanceState::Created,
+        "starting" => InstanceState::Starting,
+        "started" => InstanceState::Started,
+        "stopping" => InstanceState::Stopping,
+        "stopped" => InstanceState::Stopped,
+        "suspending" => InstanceState::Suspending,
+        "suspended" => InstanceState::Suspended,
+        "replacing" => InstanceState::Replacing,
+        "destroying" => InstanceState::Destroying,
+        "destroyed" => InstanceState::Destroyed,
+        "failed" | "launch_failed" => InstanceState::Failed,
+        _ => InstanceState::Unknown,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    Table,
+    Json,
+    Yaml,
+    Csv,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConnectorKind {
+    Flyctl,
+    Ssh,
+}
+
+impl FromStr for ConnectorKind {
+    type Err = String;
+
+    fn from_str(kind: &str) -> Result<Self, Self::Err> {
+        match kind {
+            "flyctl" => Ok(ConnectorKind::Flyctl),
+            "ssh" => Ok(ConnectorKind::Ssh),
+            _ => Err("Connector must be one of [flyctl, ssh]".to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Protocol {
+    Tcp,
+    Udp,
+}
+
+// a port exposed on the app's shared ip, written as [public:]internal[/handlers|tcp|udp]
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PortSpec {
+    pub public_port: u16,
+    pub internal_port: u16,
+    pub protocol: Protocol,
+    pub handlers: Vec<String>,
+}
+
+const HANDLERS: [&str; 5] = ["http", "tls", "pg_tls", "proxy_proto", "edge_http"];
+
+impl FromStr for PortSpec {
+    type Err = String;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let (ports, options) = match spec.split_once('/') {
+            Some((ports, options)) => (ports, Some(options)),
+            None => (spec, None),
+        };
+        let parse = |port: &str| {
+            port.parse::<u16>()
+                .ok()
+                .filter(|port| *port > 0)
+                .ok_or_else(|| format!("Invalid port {}", port))
+        };
+        let (public_port, internal_port) = match ports.split_once(':') {
+            Some((public, internal)) => (parse(public)?, parse(internal)?),
+            None => (parse(ports)?, parse(ports)?),
+        };
+        if internal_port < 1024 {
+            return Err("Port must be between 1024 and 65535".to_string());
+        }
+
+        let (protocol, handlers) = match options {
+            None => (Protocol::Tcp, vec![String::from("http")]),
+            Some("tcp") => (Protocol::Tcp, Vec::new()),
+            Some("udp") => (Protocol::Udp, Vec::new()),
+            Some(handlers) => {
+                let handlers: Vec<String> = handlers.split(',').map(String::from).collect();
+                if let Some(handler) = handlers
+                    .iter()
+                    .find(|handler| !HANDLERS.contains(&handler.as_str()))
+                {
+                    return Err(format!(
+                        "Unknown handler {}, expected tcp, udp or any of [{}]",
+                        handler,
+                        HANDLERS.join(", ")
+                    ));
+                }
+                (Protocol::Tcp, handlers)
+            }
+        };
+
+        Ok(PortSpec {
+            public_port,
+            internal_port,
+            protocol,
+            handlers,
+        })
+    }
+}
+
+impl fmt::Display for PortSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.public_port == self.internal_port {
+            write!(f, "{}", self.internal_port)?;
+        } else {
+            write!(f, "{}:{}", self.public_port, self.internal_port)?;
+        }
+        match self.protocol {
+            Protocol::Udp => write!(f, "/udp"),
+            Protocol::Tcp if self.handlers == ["http"] => Ok(()),
+            Protocol::Tcp if self.handlers.is_empty() => write!(f, "/tcp"),
+            Protocol::Tcp => write!(f, "/{}", self.handlers.join(",")),
+        }
     }
 }
 
-#[derive(Serialize, Deserialize)]
+// a port on the instance made reachable on a local port
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PortForward {
+    pub remote: u16,
+    pub local: u16,
+}
+
+// the settings of a single profile
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Config {
+    // only set in config files from before keyring support, and moved out on first use
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub fly_api_key: Option<String>,
+    // where the api key is kept instead
+    pub credential: Option<CredentialStore>,
     pub fly_app_name: Option<String>,
+    pub fly_org: Option<String>,
+    pub fly_api_url: Option<String>,
+    pub fly_graphql_url: Option<String>,
+    pub connector: Option<String>,
+    pub ssh_user: Option<String>,
+    pub ssh_identity: Option<String>,
+    // defaults for `new`, asked for when missing
+    pub region: Option<String>,
+    pub image: Option<String>,
+    pub cpus: Option<u32>,
+    pub memory: Option<u32>,
+    pub volume: Option<u32>,
 }
 
 impl Config {
-    pub fn new() -> Self {
-        Config {
-            fly_api_key: None,
-            fly_app_name: None,
-        }
+    // fills in whatever `other` sets, leaving the rest alone
+    pub fn merge(&mut self, other: Config) {
+        self.fly_api_key = other.fly_api_key.or(self.fly_api_key.take());
+        self.credential = other.credential.or(self.credential.take());
+        self.fly_app_name = other.fly_app_name.or(self.fly_app_name.take());
+        self.fly_org = other.fly_org.or(self.fly_org.take());
+        self.fly_api_url = other.fly_api_url.or(self.fly_api_url.take());
+        self.fly_graphql_url = other.fly_graphql_url.or(self.fly_graphql_url.take());
+        self.connector = other.connector.or(self.connector.take());
+        self.ssh_user = other.ssh_user.or(self.ssh_user.take());
+        self.ssh_identity = other.ssh_identity.or(self.ssh_identity.take());
+        self.region = other.region.or(self.region.take());
+        self.image = other.image.or(self.image.take());
+        self.cpus = other.cpus.or(self.cpus.take());
+        self.memory = other.memory.or(self.memory.take());
+        self.volume = other.volume.or(self.volume.take());
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CredentialStore {
+    Keyring,
+    File,
+}
+
+// what `spsd profile list` shows, leaving out the api key
+#[derive(Debug, Serialize)]
+pub struct ProfileSummary {
+    pub name: String,
+    pub active: bool,
+    pub app: Option<String>,
+    pub org: Option<String>,
+    pub region: Option<String>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+pub struct ConfigFile {
+    pub current_profile: Option<String>,
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Config>,
+}
+
 // structs for parsing fly's api responses from fade (https://github.com/nebulatgs/fade/)
 pub type Machines = Vec<Machine>;
 
@@ -99,6 +496,8 @@ pub struct Machine {
     pub config: MachineConfig,
     pub image_ref: ImageRef,
     pub created_at: String,
+    // when the machine last changed, e.g. was stopped
+    pub updated_at: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -120,6 +519,7 @@ pub struct MachineConfig {
     pub mounts: Vec<Mount>,
     pub restart: Option<Restart>,
     pub guest: Option<Guest>,
+    pub files: Option<Vec<MachineFile>>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -132,6 +532,7 @@ pub struct Service {
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Port {
     pub port: u16,
+    pub handlers: Option<Vec<String>>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -149,6 +550,12 @@ pub struct Init {
     pub tty: Option<bool>,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MachineFile {
+    pub guest_path: String,
+    pub raw_value: Option<String>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Restart {
     pub policy: Option<String>,
@@ -164,6 +571,101 @@ pub struct Mount {
     pub size_gb_limit: u32,
 }
 
+// an app secret as reported by fly, which never includes its value
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Secret {
+    pub name: String,
+    pub digest: String,
+    pub created_at: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct App {
+    pub name: String,
+    pub organization: Organization,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Organization {
+    pub slug: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IpType {
+    V4,
+    V6,
+    SharedV4,
+    PrivateV6,
+}
+
+impl fmt::Display for IpType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IpType::V4 => write!(f, "v4"),
+            IpType::V6 => write!(f, "v6"),
+            IpType::SharedV4 => write!(f, "shared v4"),
+            IpType::PrivateV6 => write!(f, "private v6"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IpAddress {
+    // the shared ipv4 address has no id of its own
+    #[serde(default)]
+    pub id: String,
+    pub address: String,
+    #[serde(rename = "type")]
+    pub ip_type: IpType,
+    pub region: Option<String>,
+    pub created_at: Option<String>,
+}
+
+impl IpAddress {
+    // whether traffic for `port` can reach the app on this address. fly only routes udp over
+    // dedicated ipv4 addresses, and the shared one only serves http and tls on 80 and 443
+    pub fn reaches(&self, port: &PortSpec) -> bool {
+        match (self.ip_type, port.protocol) {
+            (IpType::V4, _) => true,
+            (IpType::V6, Protocol::Tcp) => true,
+            (IpType::SharedV4, Protocol::Tcp) => {
+                matches!(port.public_port, 80 | 443)
+                    && port
+                        .handlers
+                        .iter()
+                        .any(|handler| handler == "http" || handler == "tls")
+            }
+            _ => false,
+        }
+    }
+
+    pub fn endpoint(&self, port: u16) -> String {
+        if self.address.contains(':') {
+            format!("[{}]:{}", self.address, port)
+        } else {
+            format!("{}:{}", self.address, port)
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub id: String,
+    pub created_at: String,
+    pub size: u64,
+    pub status: String,
+    pub retention_days: Option<u32>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct VolumeExtension {
+    pub volume: Volume,
+    pub needs_restart: bool,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Volume {
     pub id: String,
@@ -0,0 +1,325 @@
+use super::config::get_data_path;
+use super::error::SpsdError;
+use super::types::{Instance, InstanceSpecs, InstanceState};
+use chrono::{DateTime, Datelike, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+
+const LEDGER_FILE: &str = "spsd_usage.jsonl";
+const PRICES: &str = include_str!("../../resources/prices.json");
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UsageEventKind {
+    Start,
+    Stop,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UsageEvent {
+    pub instance: String,
+    pub machine_id: String,
+    pub kind: UsageEventKind,
+    pub timestamp: DateTime<Utc>,
+    pub specs: InstanceSpecs,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct UsageSummary {
+    pub instance: String,
+    pub month: String,
+    pub hours: f64,
+    pub specs: InstanceSpecs,
+    pub compute_cost: f64,
+    pub volume_cost: f64,
+    pub total_cost: f64,
+}
+
+#[derive(Deserialize)]
+struct PriceTable {
+    cpu_hour: HashMap<String, f64>,
+    memory_gb_hour: f64,
+    volume_gb_month: f64,
+}
+
+impl PriceTable {
+    fn hourly(&self, specs: &InstanceSpecs) -> f64 {
+        let cpu_hour = self
+            .cpu_hour
+            .get(&specs.cpu_kind)
+            .or_else(|| self.cpu_hour.get("shared"))
+            .copied()
+            .unwrap_or_default();
+        specs.cpu_count as f64 * cpu_hour + specs.memory_mb as f64 / 1024.0 * self.memory_gb_hour
+    }
+}
+
+pub fn record_event(instance: &Instance, kind: UsageEventKind) -> Result<(), SpsdError> {
+    let event = UsageEvent {
+        instance: instance.name.clone(),
+        machine_id: instance.machine_id.clone(),
+        kind,
+        timestamp: Utc::now(),
+        specs: instance.specs.clone(),
+    };
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(get_data_path(LEDGER_FILE)?)?;
+    writeln!(file, "{}", serde_json::to_string(&event)?)?;
+    Ok(())
+}
+
+pub fn read_events() -> Result<Vec<UsageEvent>, SpsdError> {
+    let path = get_data_path(LEDGER_FILE)?;
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let mut events = Vec::new();
+    for line in BufReader::new(File::open(path)?).lines() {
+        let line = line?;
+        if !line.trim().is_empty() {
+            events.push(serde_json::from_str(&line)?);
+        }
+    }
+    Ok(events)
+}
+
+// what fly currently reports about a machine, used to close sessions spsd never saw end
+pub struct MachineActivity {
+    pub machine_id: String,
+    pub state: InstanceState,
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+// adds the stops the ledger is missing for machines that aren't running anymore, e.g. because the
+// idle watchdog stopped them or they were stopped from the dashboard or flyctl. such a session ends
+// when fly last saw the machine change. if fly can't tell (e.g. the machine is gone), nothing in
+// the ledger can either, so the session's start is taken out and returned instead of guessing
+pub fn reconcile(events: &mut Vec<UsageEvent>, machines: &[MachineActivity]) -> Vec<UsageEvent> {
+    let mut latest: HashMap<&str, &UsageEvent> = HashMap::new();
+    for event in events.iter() {
+        let entry = latest.entry(&event.machine_id).or_insert(event);
+        if event.timestamp >= entry.timestamp {
+            *entry = event;
+        }
+    }
+
+    let mut stops = Vec::new();
+    let mut unknown = Vec::new();
+    for start in latest.into_values() {
+        if start.kind != UsageEventKind::Start {
+            continue;
+        }
+        let machine = machines
+            .iter()
+            .find(|machine| machine.machine_id == start.machine_id);
+        if machine.is_some_and(|machine| machine.state.is_running()) {
+            continue;
+        }
+        // the start is the machine's latest event, so only fly knows when it stopped
+        match machine.and_then(|machine| machine.updated_at) {
+            Some(end) => stops.push(UsageEvent {
+                kind: UsageEventKind::Stop,
+                timestamp: end.max(start.timestamp),
+                ..start.clone()
+            }),
+            None => unknown.push(start.clone()),
+        }
+    }
+    events.retain(|event| {
+        !unknown.iter().any(|start| {
+            event.kind == UsageEventKind::Start
+                && event.machine_id == start.machine_id
+                && event.timestamp == start.timestamp
+        })
+    });
+    events.extend(stops);
+    unknown.sort_by_key(|start| start.timestamp);
+    unknown
+}
+
+// pairs starts with stops per machine and totals hours and estimated cost per instance and month.
+// a start without a stop is counted until the next start of the same machine, or until `now`.
+pub fn summarize(events: &[UsageEvent], now: DateTime<Utc>) -> Vec<UsageSummary> {
+    let prices: PriceTable = serde_json::from_str(PRICES).expect("Invalid bundled price table");
+
+    let mut events: Vec<&UsageEvent> = events.iter().collect();
+    events.sort_by_key(|event| event.timestamp);
+
+    let mut open_sessions: HashMap<&str, &UsageEvent> = HashMap::new();
+    let mut sessions = Vec::new();
+    for event in events {
+        let previous = match event.kind {
+            UsageEventKind::Start => open_sessions.insert(&event.machine_id, event),
+            UsageEventKind::Stop => open_sessions.remove(event.machine_id.as_str()),
+        };
+        if let Some(start) = previous {
+            sessions.push((start, event.timestamp));
+        }
+    }
+    sessions.extend(open_sessions.into_values().map(|start| (start, now)));
+
+    let mut summaries: BTreeMap<(String, String), UsageSummary> = BTreeMap::new();
+    for (start, end) in sessions {
+        for (month, hours) in split_by_month(start.timestamp, end) {
+            let summary = summaries
+                .entry((start.instance.clone(), month.clone()))
+                .or_insert_with(|| UsageSummary {
+                    instance: start.instance.clone(),
+                    month,
+                    hours: 0.0,
+                    specs: start.specs.clone(),
+                    compute_cost: 0.0,
+                    volume_cost: start.specs.volume_gb as f64 * prices.volume_gb_month,
+                    total_cost: 0.0,
+                });
+            summary.hours += hours;
+            summary.compute_cost += hours * prices.hourly(&start.specs);
+        }
+    }
+
+    summaries
+        .into_values()
+        .map(|mut summary| {
+            summary.total_cost = summary.compute_cost + summary.volume_cost;
+            summary
+        })
+        .collect()
+}
+
+fn split_by_month(start: DateTime<Utc>, end: DateTime<Utc>) -> Vec<(String, f64)> {
+    let mut months = Vec::new();
+    let mut current = start;
+    while current < end {
+        let (year, month) = match current.month() {
+            12 => (current.year() + 1, 1),
+            month => (current.year(), month + 1),
+        };
+        let next_month = Utc.with_ymd_and_hms(year, month, 1, 0, 0, 0).unwrap();
+        let until = next_month.min(end);
+        let hours = (until - current).num_seconds() as f64 / 3600.0;
+        months.push((current.format("%Y-%m").to_string(), hours));
+        current = until;
+    }
+    months
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(kind: UsageEventKind, timestamp: &str) -> UsageEvent {
+        UsageEvent {
+            instance: String::from("devbox"),
+            machine_id: String::from("148e21"),
+            kind,
+            timestamp: timestamp.parse().unwrap(),
+            specs: InstanceSpecs {
+                cpu_kind: String::from("shared"),
+                cpu_count: 2,
+                memory_mb: 1024,
+                volume_gb: 10,
+            },
+        }
+    }
+
+    #[test]
+    fn sessions_are_split_across_months() {
+        let events = vec![
+            event(UsageEventKind::Start, "2024-01-31T23:00:00Z"),
+            event(UsageEventKind::Stop, "2024-02-01T02:00:00Z"),
+            event(UsageEventKind::Start, "2024-02-10T10:00:00Z"),
+            event(UsageEventKind::Stop, "2024-02-10T11:30:00Z"),
+        ];
+
+        let summaries = summarize(&events, Utc::now());
+
+        assert_eq!(summaries.len(), 2);
+        assert_eq!(summaries[0].month, "2024-01");
+        assert_eq!(summaries[0].hours, 1.0);
+        assert_eq!(summaries[1].month, "2024-02");
+        assert_eq!(summaries[1].hours, 3.5);
+        let hourly = 2.0 * 0.00098 + 0.00671;
+        assert!((summaries[1].compute_cost - 3.5 * hourly).abs() < 1e-9);
+        assert!((summaries[1].volume_cost - 1.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn sessions_of_machines_that_stopped_on_their_own_are_closed() {
+        let mut events = vec![
+            event(UsageEventKind::Start, "2024-03-01T00:00:00Z"),
+            UsageEvent {
+                machine_id: String::from("9080e6"),
+                ..event(UsageEventKind::Start, "2024-03-01T01:00:00Z")
+            },
+            UsageEvent {
+                machine_id: String::from("5683d5"),
+                ..event(UsageEventKind::Start, "2024-03-01T04:00:00Z")
+            },
+        ];
+        let machines = vec![
+            MachineActivity {
+                machine_id: String::from("148e21"),
+                state: InstanceState::Stopped,
+                updated_at: Some("2024-03-01T02:00:00Z".parse().unwrap()),
+            },
+            MachineActivity {
+                machine_id: String::from("5683d5"),
+                state: InstanceState::Started,
+                updated_at: None,
+            },
+        ];
+
+        reconcile(&mut events, &machines);
+        let summaries = summarize(&events, "2024-03-01T05:00:00Z".parse().unwrap());
+
+        // 2 hours until the watchdog stopped it and 1 for the one that's still running, while the
+        // destroyed machine's session has no known end and isn't counted
+        assert_eq!(summaries.len(), 1);
+        assert_eq!(summaries[0].hours, 3.0);
+    }
+
+    #[test]
+    fn sessions_are_not_closed_by_other_machines_events() {
+        let mut events = vec![
+            event(UsageEventKind::Start, "2024-03-01T00:00:00Z"),
+            UsageEvent {
+                instance: String::from("scratch"),
+                machine_id: String::from("9080e6"),
+                ..event(UsageEventKind::Start, "2024-03-01T01:00:00Z")
+            },
+            UsageEvent {
+                instance: String::from("scratch"),
+                machine_id: String::from("9080e6"),
+                ..event(UsageEventKind::Stop, "2024-03-01T03:00:00Z")
+            },
+        ];
+        // devbox's machine was destroyed outside spsd, so fly doesn't list it anymore
+        let machines = vec![MachineActivity {
+            machine_id: String::from("9080e6"),
+            state: InstanceState::Stopped,
+            updated_at: Some("2024-03-01T03:00:00Z".parse().unwrap()),
+        }];
+
+        let unknown = reconcile(&mut events, &machines);
+        assert_eq!(unknown.len(), 1);
+        assert_eq!(unknown[0].instance, "devbox");
+        let summaries = summarize(&events, "2024-03-01T05:00:00Z".parse().unwrap());
+        assert_eq!(summaries.len(), 1);
+        assert_eq!(summaries[0].instance, "scratch");
+        assert_eq!(summaries[0].hours, 2.0);
+    }
+
+    #[test]
+    fn unmatched_start_is_counted_until_now() {
+        let events = vec![event(UsageEventKind::Start, "2024-03-01T00:00:00Z")];
+
+        let summaries = summarize(&events, "2024-03-01T05:00:00Z".parse().unwrap());
+
+        assert_eq!(summaries.len(), 1);
+        assert_eq!(summaries[0].hours, 5.0);
+    }
+}